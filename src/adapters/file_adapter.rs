@@ -1,167 +1,1000 @@
 use crate::{
-    constants::BUF_SIZE,
-    recorder::{Block, Input, Output},
-    utils::{bytes_to_u32, u32_to_bytes},
+    format::{
+        encode_block, quarantine_path, repair_tail, Compression, Corruption, FileHeader, RecordReader, RecordWriter,
+        TailRepair, FORMAT_VERSION, MAX_BLOCK_FILL,
+    },
+    index::{Index, IndexWriter},
+    message::Message,
+    pcap::{input_addresses, pcapng_complete_length, CaptureFormat, CaptureWriter},
+    recorder::{Block, BusWriter, FsyncPolicy, Input, Mode, OnWriteError, Output},
+    replay::{replay, ReplaySource, Replayed, WindowPosition},
+    retention::Retention,
+    rollover::{Rollover, Zone},
+    status::{StatusReporter, WriteState},
+    template,
 };
-use bus::{Bus, BusReader};
-use chrono::Local;
+use bus::BusReader;
 use std::{
-    fs::OpenOptions,
-    io::{stdin, BufReader, Error, Read, Seek, Write},
+    fs::{self, File, OpenOptions},
+    collections::VecDeque,
+    io::{BufReader, Error, ErrorKind, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{mpsc::RecvTimeoutError, Arc, Mutex},
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// How often an idle output checks whether rollover is due, flush and fsync intervals may be shorter
+const ROLLOVER_POLL: Duration = Duration::from_secs(1);
+/// Shortest flush interval of compressed output, blocks of a few records compress worse than no compression
+const MIN_BLOCK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Files synced by this thread, to test fsync policies
+#[cfg(test)]
+thread_local!(static SYNCS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) });
+
 #[derive(Debug)]
 pub struct FileAdapter {}
 
-impl Output for FileAdapter {
-    fn write(
-        &self,
-        block: Block,
-        channel: &mut BusReader<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), Error> {
-        let date_string = format!("{:?}", Local::now().date_naive());
-        let file_path = &block.file_path.replace("$date", date_string.as_str());
-
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(true)
-            .open(file_path)
-            .unwrap();
-
-        let mut prev_time = Instant::now();
+/// Recording the file output currently writes to.
+/// Records are buffered and written to the file once enough of them are buffered or
+/// the flush interval passed, see `Block.max_buffered_bytes` and `Block.flush_interval_ms`.
+/// Compressed recordings write every flush as one block, at most every `MIN_BLOCK_INTERVAL`.
+/// `Mode::Pcap` outputs write pcapng captures instead of recordings.
+struct OutputFile {
+    path: String,
+    file: File,
+    /// Frames records into its buffer, None for raw and pcapng output
+    records: Option<RecordWriter<Vec<u8>>>,
+    /// Frames packets into its buffer, None for raw output and recordings
+    packets: Option<CaptureWriter<Vec<u8>>>,
+    /// Inputs of the output, packet addresses are taken from them
+    inputs: Vec<Block>,
+    /// Buffer of raw output
+    raw: Vec<u8>,
+    /// Messages in the buffer, written again elsewhere if this file fails
+    buffered: Vec<Message>,
+    index: Option<IndexWriter>,
+    /// Monotonic capture times are stored relative to this
+    opened_at: Instant,
+    opened_wall: SystemTime,
+    /// Bytes in the file, without the buffer
+    file_size: u64,
+    /// A failed write may have left part of a record after `file_size`
+    torn: bool,
+    /// Bytes the next write gets through before failing, to test partial writes
+    #[cfg(test)]
+    fail_after: Option<usize>,
+    compression: Compression,
+    compression_level: i32,
+    max_buffered_bytes: usize,
+    flush_interval: Duration,
+    fsync: FsyncPolicy,
+    fsync_interval: Duration,
+    last_flush: Instant,
+    last_sync: Instant,
+}
 
-        loop {
-            if let Ok((data, size)) = channel.recv() {
-                if block.no_headers {
-                    // Add 4 time diff bytes
-                    let diff = u32_to_bytes(prev_time.elapsed().as_millis() as u32);
-                    prev_time = Instant::now();
-                    file.write_all(&diff).unwrap();
-
-                    // Write size header
-                    file.write_all(&u32_to_bytes(size)).unwrap();
-                }
+impl OutputFile {
+    fn open(path: String, block: &Block, inputs: &[Block], opened_at: Instant) -> Result<OutputFile, Error> {
+        let file_path = &path;
+
+        if let Some(parent) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
 
-                #[cfg(debug_assertions)]
-                println!("Writing {:?} bytes to File", size);
+        let mut file = OpenOptions::new().create(true).append(true).open(file_path)?;
 
-                file.write_all(&data[0..size as usize]).unwrap();
+        let mut compression = Compression::None;
+
+        let packets = if block.mode == Mode::Pcap {
+            let mut packets = CaptureWriter::new(Vec::with_capacity(block.max_buffered_bytes), CaptureFormat::Pcapng)?;
+
+            if file.metadata()?.len() == 0 {
+                file.write_all(packets.get_ref())?;
+            } else {
+                // Same as for recordings, a partial packet at the end would make the rest unreadable
+                let complete = pcapng_complete_length(file_path)?;
+                let length = file.metadata()?.len();
+
+                if complete < length {
+                    file.set_len(complete)?;
+                    println!(
+                        "Truncated {} bytes of partial packet at the end of {}",
+                        length - complete,
+                        file_path
+                    );
+                }
             }
+
+            packets.get_mut().clear();
+            Some(packets)
+        } else {
+            None
+        };
+
+        let records = if block.no_headers && packets.is_none() {
+            let header = if file.metadata()?.len() == 0 {
+                let mut header = FileHeader::new(inputs.to_vec());
+                header.checksums = block.checksums;
+                header.compression = block.compression;
+                header.write_to(&mut file)?;
+                header
+            } else {
+                // Appending to an existing recording, never mix format versions in one file.
+                // Version 3 files are uncompressed version 4 files. Checksums and compression
+                // follow the existing file.
+                let header = FileHeader::read_path(file_path)?;
+
+                if header.version < 3 || header.version > FORMAT_VERSION {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "{} has recording format version {}, cannot append version {} records",
+                            file_path, header.version, FORMAT_VERSION
+                        ),
+                    ));
+                }
+
+                // A recorder killed mid-write leaves a partial record, appending after it
+                // would make the new records unreadable
+                if let Some(tail) = repair_tail(file_path, TailRepair::Quarantine)? {
+                    println!(
+                        "Moved {} bytes of partial record at the end of {} to {}",
+                        tail.length,
+                        file_path,
+                        quarantine_path(file_path, tail.offset)
+                    );
+                }
+
+                if header.compression != block.compression {
+                    println!(
+                        "Appending to {} compressed with {:?}, as the file already is",
+                        file_path, header.compression
+                    );
+                }
+
+                header
+            };
+
+            compression = header.compression;
+            Some(RecordWriter::new(Vec::with_capacity(block.max_buffered_bytes), &header))
+        } else {
+            None
+        };
+
+        let index = if records.is_some() && (block.index_records > 0 || block.index_interval_ms > 0) {
+            Some(IndexWriter::open(file_path, block.index_records, block.index_interval_ms)?)
+        } else {
+            None
+        };
+
+        let mut flush_interval = Duration::from_millis(block.flush_interval_ms);
+        if compression != Compression::None && flush_interval < MIN_BLOCK_INTERVAL {
+            println!(
+                "Flushing compressed {} every {:?} instead of {:?}, so blocks hold more than a few records",
+                file_path, MIN_BLOCK_INTERVAL, flush_interval
+            );
+            flush_interval = MIN_BLOCK_INTERVAL;
         }
+
+        println!("Recording to {}", file_path);
+
+        Ok(OutputFile {
+            file_size: file.metadata()?.len(),
+            torn: false,
+            #[cfg(test)]
+            fail_after: None,
+            compression,
+            compression_level: block.compression_level,
+            path,
+            file,
+            records,
+            packets,
+            inputs: inputs.to_vec(),
+            raw: vec![],
+            buffered: vec![],
+            index,
+            opened_at,
+            opened_wall: SystemTime::now(),
+            max_buffered_bytes: block.max_buffered_bytes,
+            flush_interval,
+            fsync: block.fsync,
+            fsync_interval: Duration::from_millis(block.fsync_interval_ms),
+            last_flush: Instant::now(),
+            last_sync: Instant::now(),
+        })
     }
-}
 
-impl Input for FileAdapter {
-    fn read(&self, block: Block, channel: &mut Bus<([u8; BUF_SIZE], u32)>) -> Result<(), Error> {
-        let date_string = format!("{:?}", Local::now().date_naive());
-        let file_path = &block.file_path.replace("$date", date_string.as_str());
+    /// Bytes in the file and in the buffer
+    fn size(&self) -> u64 {
+        let buffered = match (self.records.as_ref(), self.packets.as_ref()) {
+            (Some(records), _) => records.get_ref().len(),
+            (_, Some(packets)) => packets.get_ref().len(),
+            _ => self.raw.len(),
+        };
 
-        let file = OpenOptions::new()
-            .read(true)
-            .open(file_path)
-            .expect("cannot open file");
+        self.file_size + buffered as u64
+    }
 
-        // 128kb buffer
-        let mut buf_reader = BufReader::with_capacity(131072, file);
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        match (self.records.as_mut(), self.packets.as_mut()) {
+            (Some(records), _) => records.get_mut(),
+            (_, Some(packets)) => packets.get_mut(),
+            _ => &mut self.raw,
+        }
+    }
 
-        let mut count: i32 = 0;
+    fn write(&mut self, message: &Message) -> Result<(), Error> {
+        #[cfg(debug_assertions)]
+        println!(
+            "Writing {:?} bytes to File, input {} seq {} from {:?} captured at {:?}",
+            message.size(), message.input_id, message.seq, message.source, message.wall_time
+        );
 
-        loop {
-            // enter / 0 = 1 packet
-            // 2 = 2 packets
-            // -1 = infinite
-            if block.controlled_play {
+        let buffered = self.buffer().len();
 
-                if count == 0 {
-                    let mut count_str = String::new();
-                    println!("Waiting for key press");
+        let timestamp = message.wall_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
 
-                    stdin().read_line(&mut count_str).unwrap();
+        let times = match (self.records.as_mut(), self.packets.as_mut()) {
+            (Some(records), _) => {
+                let monotonic = message.mono_time.saturating_duration_since(self.opened_at).as_nanos() as u64;
 
-                    count = count_str.trim().parse().unwrap_or(1) - 1;
-                }else if count > 0 {
-                    count -= 1;
+                records.write(timestamp, monotonic, message.input_id as u16, message.payload())?;
+                Some((timestamp, monotonic))
+            }
+            (_, Some(packets)) => {
+                let (source, mut destination) = input_addresses(self.inputs.get(message.input_id), message.source);
+                if let Some(SocketAddr::V4(replayed)) = message.destination {
+                    destination = replayed;
                 }
+
+                packets.write_packet(timestamp, source, destination, message.payload())?;
+                None
             }
+            _ => {
+                self.raw.extend_from_slice(message.payload());
+                None
+            }
+        };
 
-            // If file is in timed format than remove first 4 bytes
-            let mut diff_buf = [0; 4];
+        // A compressed block starting with this record is written where the file ends now
+        let block_offset = self.file_size;
+        self.buffered.push(message.clone());
 
-            if let Ok(sz) = buf_reader.read(&mut diff_buf) {
-                sz
-            } else {
-                if block.play_loop {
-                    buf_reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-                    continue;
-                } else {
-                    println!("File ended, waiting for changes");
-                    thread::sleep(Duration::from_secs(2));
-                    continue;
-                };
+        if let Err(e) = self.flush_if_due() {
+            // Whoever handles the error decides about this record, the ones before it stay buffered
+            self.buffer().truncate(buffered);
+            self.buffered.pop();
+            return Err(e);
+        }
+
+        if let (Some(index), Some((timestamp, monotonic))) = (self.index.as_mut(), times) {
+            if self.compression != Compression::None && buffered == 0 {
+                index.start_block(block_offset);
+            }
+
+            if let Err(e) = index.add(timestamp, monotonic, message.payload().len()) {
+                // Records matter more than the index, it is rebuilt when the recording is reopened
+                println!("Index of {} failed: {}, not indexing it any further", self.path, e);
+                self.index = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush_if_due(&mut self) -> Result<(), Error> {
+        let max_buffered_bytes = match self.compression {
+            Compression::None => self.max_buffered_bytes,
+            _ => self.max_buffered_bytes.min(MAX_BLOCK_FILL),
+        };
+
+        if self.buffer().len() >= max_buffered_bytes || self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write buffered records to the file, and sync it if the fsync interval passed
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.buffer().is_empty() {
+            // Never append after a partial record, readers could not find the records after it
+            if self.torn {
+                self.file.set_len(self.file_size)?;
+                self.torn = false;
+            }
+
+            let mut buffer = std::mem::take(self.buffer());
+
+            let result = match self.compression {
+                Compression::None => self.write_all(&buffer).map(|_| buffer.len()),
+                compression => encode_block(compression, self.compression_level, &buffer)
+                    .and_then(|block| self.write_all(&block).map(|_| block.len())),
             };
 
-            // Size header
-            let mut size_buff = [0; 4];
-            
-            if let Ok(sz) = buf_reader.read(&mut size_buff) {
-                sz
-            } else {
-                if block.play_loop {
-                    buf_reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-                    continue;
-                } else {
-                    println!("File ended, waiting for changes");
-                    thread::sleep(Duration::from_secs(2));
-                    continue;
-                };
+            let written = match result {
+                Ok(written) => written,
+                Err(e) => {
+                    // Cut off whatever part made it, the buffer is written again on the next flush
+                    self.torn = self.file.set_len(self.file_size).is_err();
+                    *self.buffer() = buffer;
+                    return Err(e);
+                }
             };
 
-            let size = bytes_to_u32(size_buff);
+            self.file_size += written as u64;
+            buffer.clear();
+            *self.buffer() = buffer;
+            self.buffered.clear();
+        }
 
-            if size == 0 {
+        self.last_flush = Instant::now();
 
-                if block.play_loop {
-                    buf_reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-                    continue;
-                } else {
-                    println!("File ended, waiting for changes");
-                    thread::sleep(Duration::from_secs(2));
-                }
+        if self.fsync == FsyncPolicy::Interval && self.last_sync.elapsed() >= self.fsync_interval {
+            self.sync(false)?;
+            self.last_sync = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    /// Sync the file data to disk, and its metadata if `all`
+    fn sync(&self, all: bool) -> Result<(), Error> {
+        #[cfg(test)]
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
+
+        match all {
+            true => self.file.sync_all(),
+            false => self.file.sync_data(),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        #[cfg(test)]
+        if let Some(limit) = self.fail_after.take() {
+            self.file.write_all(&bytes[..limit.min(bytes.len())])?;
+            return Err(Error::new(ErrorKind::StorageFull, "no space left on device"));
+        }
+
+        self.file.write_all(bytes)
+    }
+
+    /// Flush and, unless fsync is disabled, sync the file before it is closed
+    fn close(mut self) -> Result<(), Error> {
+        self.flush()?;
+
+        if self.fsync != FsyncPolicy::Never {
+            self.sync(true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Give up on the file, the buffered messages are returned instead of written
+    fn abandon(mut self) -> Vec<Message> {
+        self.buffer().clear();
+        std::mem::take(&mut self.buffered)
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if !self.buffered.is_empty() {
+            if let Err(e) = self.flush() {
+                println!("Lost {} buffered records of {}: {}", self.buffered.len(), self.path, e);
             }
+        }
+    }
+}
 
-            if block.play_timed {
-                let mut diff = bytes_to_u32(diff_buf);
+/// Paths of the successive files of an output
+struct OutputPaths<'a> {
+    template: &'a str,
+    /// Block the data comes from, for placeholders
+    source: &'a Block,
+    zone: Zone,
+    sequence: u32,
+    /// Template expanded for the current file
+    expanded: String,
+    /// Directory files are put in instead of the template's, after failing over
+    directory: Option<PathBuf>,
+}
 
-                // If multiplier is more than 1, then lower limit of time diff should be 1 atleast
-                // because multiplying by 0 is useless for slowing speed
-                if block.speed_multiplier > 1.0 {
-                    diff = diff.max(1);
-                }
+impl<'a> OutputPaths<'a> {
+    fn new(template: &'a str, source: &'a Block, zone: Zone) -> OutputPaths<'a> {
+        OutputPaths {
+            template,
+            source,
+            zone,
+            sequence: 0,
+            expanded: String::new(),
+            directory: None,
+        }
+    }
+
+    /// Path of the file opened at `now`.
+    /// The sequence number counts up while the template expands to the same path, it is appended
+    /// if the template has no `$seq`, so size and duration rollover never reopen the file just closed.
+    fn next(&mut self, now: SystemTime) -> Result<String, Error> {
+        let time = self.zone.local_time(now);
+
+        let expanded = template::expand(self.template, self.source, time, self.sequence)?;
+        if expanded == self.expanded {
+            self.sequence += 1;
+            self.expanded = template::expand(self.template, self.source, time, self.sequence)?;
+        } else {
+            self.sequence = 0;
+            self.expanded = template::expand(self.template, self.source, time, 0)?;
+        }
+
+        let path = if self.sequence == 0 || template::has_sequence(self.template) {
+            self.expanded.clone()
+        } else {
+            format!("{}.{}", self.expanded, self.sequence)
+        };
+
+        Ok(match &self.directory {
+            Some(directory) => directory
+                .join(Path::new(&path).file_name().unwrap_or_default())
+                .to_string_lossy()
+                .to_string(),
+            None => path,
+        })
+    }
+}
+
+/// File output state across rollovers and write errors
+pub struct FileWriter<'a> {
+    block: &'a Block,
+    inputs: &'a [Block],
+    rollover: Rollover,
+    paths: OutputPaths<'a>,
+    /// None until the next file is opened, after rollover, failover or failing to open
+    output: Option<OutputFile>,
+    /// Path of the next file to open
+    next_path: String,
+    /// Monotonic capture times of the next file are stored relative to this
+    next_opened_at: Instant,
+    /// Path of the file being written, shared with retention
+    current: Arc<Mutex<String>>,
+    /// Buffered messages of a file failed over from, written to the next file first
+    carried: VecDeque<Message>,
+    status: StatusReporter,
+}
+
+impl<'a> FileWriter<'a> {
+    pub fn new(block: &'a Block, inputs: &'a [Block]) -> Result<FileWriter<'a>, Error> {
+        let rollover = Rollover::from_block(block)?;
+        let mut paths = OutputPaths::new(&block.file_path, inputs.first().unwrap_or(block), rollover.zone);
+        let now = SystemTime::now();
+
+        let mut writer = FileWriter {
+            block,
+            inputs,
+            next_path: paths.next(now)?,
+            next_opened_at: Instant::now(),
+            rollover,
+            paths,
+            output: None,
+            current: Arc::new(Mutex::new(String::new())),
+            carried: VecDeque::new(),
+            status: StatusReporter::new(&block.status_file),
+        };
+
+        writer.rollover.opened(now);
+        writer.status.set_path(&writer.next_path);
+        *writer.current.lock().unwrap() = writer.next_path.clone();
+
+        Ok(writer)
+    }
+
+    /// Close the current file if rollover is due for something captured at `now`,
+    /// the next file is opened for the next write
+    fn roll_over(&mut self, now: SystemTime, opened_at: Instant) -> Result<(), Error> {
+        let Some(output) = &self.output else {
+            return Ok(());
+        };
+
+        if !self.rollover.due(output.size(), output.opened_wall, now) {
+            return Ok(());
+        }
+
+        self.next_path = self.paths.next(now)?;
+        self.next_opened_at = opened_at;
+        println!("Rolling over from {} to {}", output.path, self.next_path);
+
+        self.close();
+        self.rollover.opened(now);
+
+        Ok(())
+    }
+
+    /// Flush and close the current file, the next write opens the next one
+    pub fn close(&mut self) {
+        let Some(output) = self.output.take() else {
+            return;
+        };
+
+        let path = output.path.clone();
+        if let Err(e) = output.close() {
+            println!("Closing {} failed: {}", path, e);
+            self.status.error(&e);
+        }
+    }
+
+    /// Write everything buffered, handling errors as configured, then close and sync the file
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if let Some(output) = self.output.as_mut() {
+            output.flush_interval = Duration::ZERO;
+        }
+
+        // Don't open the next file only to close it again
+        self.write_with(None, false)?;
+        self.close();
+
+        Ok(())
+    }
+
+    /// How long to wait for messages before checking rollover and flush intervals
+    fn poll_interval(&self) -> Duration {
+        let mut interval = ROLLOVER_POLL.min(Duration::from_millis(self.block.flush_interval_ms));
+        if self.block.fsync == FsyncPolicy::Interval {
+            interval = interval.min(Duration::from_millis(self.block.fsync_interval_ms));
+        }
+
+        interval.max(Duration::from_millis(1))
+    }
+
+    /// Continue in the failover directory with the next write, fails if already there
+    fn fail_over(&mut self) -> Result<(), Error> {
+        if self.block.failover_path.is_empty() || self.paths.directory.is_some() {
+            return Err(Error::other("no failover directory left"));
+        }
+
+        // Same file name in the failover directory, the sequence starts over there
+        self.paths.directory = Some(PathBuf::from(&self.block.failover_path));
+        self.paths.expanded.clear();
+        self.next_path = self.paths.next(SystemTime::now())?;
 
-                #[cfg(debug_assertions)]
-                println!("Sleeping for {} ms", diff as f64 * block.speed_multiplier);
-                thread::sleep(Duration::from_millis(
-                    (diff as f64 * block.speed_multiplier) as u64,
-                ));
+        // Records buffered for the failed file go to the failover file, before the failed one
+        if let Some(output) = self.output.take() {
+            self.carried.extend(output.abandon());
+        }
+
+        let now = Instant::now();
+        self.next_opened_at = self.carried.front().map_or(now, |message| message.mono_time.min(now));
+
+        Ok(())
+    }
+
+    /// Roll over if due, open the next file if needed and write a message to it.
+    /// Without a message only flushes if the flush interval passed.
+    fn try_write(&mut self, message: Option<&Message>, roll_over: bool) -> Result<(), Error> {
+        match message {
+            Some(message) if roll_over => self.roll_over(message.wall_time, message.mono_time.min(Instant::now()))?,
+            None if roll_over => self.roll_over(SystemTime::now(), Instant::now())?,
+            _ => {}
+        }
+
+        if self.output.is_none() {
+            let output = OutputFile::open(self.next_path.clone(), self.block, self.inputs, self.next_opened_at)?;
+            *self.current.lock().unwrap() = output.path.clone();
+            self.status.set_path(&output.path);
+            self.output = Some(output);
+        }
+
+        if let Some(output) = self.output.as_mut() {
+            while let Some(carried) = self.carried.front() {
+                output.write(carried)?;
+                self.carried.pop_front();
             }
+        }
+
+        match (self.output.as_mut(), message) {
+            (Some(output), Some(message)) => output.write(message),
+            (Some(output), None) => output.flush_if_due(),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Write a message, or open the next file and flush, handling errors as configured by `on_write_error`.
+    /// Rollover happens here too, so failing to open the next file is handled the same way.
+    pub fn write(&mut self, message: Option<&Message>) -> Result<(), Error> {
+        self.write_with(message, true)
+    }
 
-            let mut buf = [0; BUF_SIZE];
-            if let Err(_) = buf_reader.read_exact(&mut buf[..size as usize]) {
-                if block.play_loop {
-                    buf_reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-                    continue;
-                } else {
-                    println!("File ended, waiting for changes");
-                    thread::sleep(Duration::from_secs(2));
+    fn write_with(&mut self, message: Option<&Message>, roll_over: bool) -> Result<(), Error> {
+        loop {
+            let error = match self.try_write(message, roll_over) {
+                Ok(()) => {
+                    if message.is_some() {
+                        self.status.written();
+                    }
+
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            self.status.error(&error);
+
+            match self.block.on_write_error {
+                OnWriteError::Fail => {
+                    self.status.set_state(WriteState::Failed);
+                    return Err(Error::new(error.kind(), format!("writing {} failed: {}", self.status.status.path, error)));
+                }
+                OnWriteError::Failover => {
+                    self.status.set_state(WriteState::FailedOver);
+
+                    if let Err(e) = self.fail_over() {
+                        self.status.set_state(WriteState::Failed);
+                        return Err(Error::new(error.kind(), format!("writing {} failed: {}, {}", self.status.status.path, error, e)));
+                    }
+                }
+                OnWriteError::Retry => {
+                    self.status.set_state(WriteState::Retrying);
+                    self.status.tick();
+                    thread::sleep(Duration::from_millis(self.block.retry_interval_ms));
                 }
+                OnWriteError::Drop => {
+                    self.status.set_state(WriteState::Dropping);
+                    if let Some(message) = message {
+                        self.status.dropped(message.payload().len());
+                    }
+
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Output for FileAdapter {
+    fn write(&self, block: Block, inputs: &[Block], channel: &mut BusReader<Message>) -> Result<(), Error> {
+        let mut writer = FileWriter::new(&block, inputs)?;
+        writer.write(None)?;
+
+        if let Some(retention) = Retention::from_block(&block)? {
+            retention.spawn(writer.current.clone());
+        }
+
+        let poll_interval = writer.poll_interval();
+
+        // Rollover happens between reads, packets arriving meanwhile wait on the bus
+        loop {
+            match channel.recv_timeout(poll_interval) {
+                Ok(message) => writer.write(Some(&message))?,
+                // Roll over and open the next file now, so retention and the status show it, and flush
+                Err(RecvTimeoutError::Timeout) => writer.write(None)?,
+                // Every input stopped and the bus is drained
+                Err(RecvTimeoutError::Disconnected) => return writer.finish(),
+            }
+
+            writer.status.tick();
+        }
+    }
+}
+
+/// Recording replayed by the file input, see `replay`
+struct FileSource {
+    path: String,
+    records: RecordReader<BufReader<File>>,
+    index: Option<Index>,
+    corruption: Corruption,
+    /// Lower limit of the time between records
+    min_delta: u64,
+}
+
+impl ReplaySource for FileSource {
+    fn next(&mut self) -> Result<Option<Replayed>, Error> {
+        let next = self.records.next_record()?;
+
+        if self.records.corruption != self.corruption {
+            println!(
+                "Skipped {} bytes of corrupt data in {}, resynchronised at offset {}",
+                self.records.corruption.skipped_bytes - self.corruption.skipped_bytes,
+                self.path,
+                self.records.offset()
+            );
+            self.corruption = self.records.corruption;
+        }
+
+        Ok(next.map(|mut record| {
+            record.delta = record.delta.max(self.min_delta);
+
+            Replayed {
+                destination: self.records.header.input_address(record.input),
+                source: None,
+                record,
+            }
+        }))
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.records.rewind()
+    }
+
+    fn seek(&mut self, start_record: Option<u64>, start: Option<&WindowPosition>) -> Result<u64, Error> {
+        let entry = self.index.as_ref().and_then(|i| i.find(start_record, start));
+
+        match entry.filter(|entry| entry.record > 0) {
+            Some(entry) => {
+                self.records.seek_to(entry)?;
+                Ok(entry.record)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Input for FileAdapter {
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), Error> {
+        let file_path = template::input_path(&block.file_path, &block)?;
+
+        let file = OpenOptions::new().read(true).open(&file_path)?;
+
+        // 128kb buffer
+        let records = RecordReader::new(BufReader::with_capacity(131072, file))?;
+
+        println!(
+            "Replaying {} with recording format version {}, compression {:?}",
+            file_path, records.header.version, records.header.compression
+        );
+
+        // Index lets replay jump close to the window start instead of reading up to it
+        let index = match Index::load(&file_path) {
+            Ok(index) => Some(index),
+            Err(_) if !block.play_start.is_empty() || block.play_start_record > 0 => {
+                println!("No usable index for {}, reading up to the replay window", file_path);
+                None
             }
+            Err(_) => None,
+        };
+
+        // Older formats store whole milliseconds, so sub millisecond gaps read as 0.
+        // If multiplier is more than 1, then lower limit of time diff should be 1 ms atleast
+        // because multiplying by 0 is useless for slowing speed
+        let min_delta = match block.speed_multiplier > 1.0 && records.header.version < 2 {
+            true => 1_000_000,
+            false => 0,
+        };
+
+        let mut source = FileSource {
+            corruption: records.corruption,
+            path: file_path.clone(),
+            records,
+            index,
+            min_delta,
+        };
+
+        replay(&block, &file_path, &mut source, channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process, sync::Arc};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn block(name: &str, settings: serde_json::Value) -> Block {
+        let directory = env::temp_dir().join(format!("recorder-file-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut block = json!({
+            "mode": "file",
+            "file_path": directory.join("out.rec").to_string_lossy(),
+            "no_headers": true,
+            "flush_interval_ms": 0,
+            "index_records": 0,
+            "index_interval_ms": 0,
+            "retry_interval_ms": 1,
+        });
+        block.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+
+        serde_json::from_value(block).unwrap()
+    }
+
+    fn message(seq: u64) -> Message {
+        Message {
+            wall_time: SystemTime::now(),
+            mono_time: Instant::now(),
+            input_id: 0,
+            source: None,
+            destination: None,
+            seq,
+            data: Arc::from(vec![seq as u8; 100]),
+        }
+    }
+
+    /// Payloads of a recording, checking it has no corrupt or partial records
+    fn payloads(path: &str) -> Vec<u8> {
+        let mut records = RecordReader::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        let mut payloads = vec![];
+
+        while let Some(record) = records.next_record().unwrap() {
+            assert_eq!(record.data.len(), 100);
+            payloads.push(record.data[0]);
+        }
+
+        assert_eq!(records.corruption.bad_records, 0, "{}", path);
+        assert_eq!(records.offset(), fs::metadata(path).unwrap().len(), "{}", path);
+        payloads
+    }
+
+    /// Write three messages, the second one fails after writing part of its record
+    fn write_torn(block: &Block) -> (FileWriter<'_>, Result<(), Error>) {
+        let mut writer = FileWriter::new(block, &[]).unwrap();
+        writer.write(None).unwrap();
+        writer.write(Some(&message(0))).unwrap();
+
+        writer.output.as_mut().unwrap().fail_after = Some(30);
+        let result = writer.write(Some(&message(1))).and_then(|_| writer.write(Some(&message(2))));
+
+        (writer, result)
+    }
+
+    #[test]
+    fn failed_writes_leave_no_partial_records() {
+        let retry = block("retry", json!({ "on_write_error": "retry" }));
+        let (mut writer, result) = write_torn(&retry);
+        result.unwrap();
+        assert_eq!(writer.status.status.state, WriteState::Ok);
+        writer.finish().unwrap();
+        assert_eq!(payloads(&retry.file_path), [0, 1, 2]);
+
+        let drop = block("drop", json!({ "on_write_error": "drop" }));
+        let (mut writer, result) = write_torn(&drop);
+        result.unwrap();
+        assert_eq!(writer.status.status.dropped_records, 1);
+        writer.finish().unwrap();
+        assert_eq!(payloads(&drop.file_path), [0, 2]);
+
+        let failover_path = env::temp_dir().join(format!("recorder-file-test-failover-to-{}", process::id()));
+        let _ = fs::remove_dir_all(&failover_path);
+        let failover = block("failover", json!({ "on_write_error": "failover", "failover_path": failover_path }));
+        let (mut writer, result) = write_torn(&failover);
+        result.unwrap();
+        assert_eq!(writer.status.status.state, WriteState::FailedOver);
+        writer.finish().unwrap();
+        assert_eq!(payloads(&failover.file_path), [0]);
+        assert_eq!(payloads(failover_path.join("out.rec").to_str().unwrap()), [1, 2]);
 
-            #[cfg(debug_assertions)]
-            println!("Reading {} bytes from File", size);
-            channel.broadcast((buf, size));
+        let fail = block("fail", json!({ "on_write_error": "fail" }));
+        let (mut writer, result) = write_torn(&fail);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::StorageFull);
+        writer.close();
+        assert_eq!(payloads(&fail.file_path), [0]);
+
+        for block in [retry, drop, failover, fail] {
+            fs::remove_dir_all(Path::new(&block.file_path).parent().unwrap()).unwrap();
         }
+        fs::remove_dir_all(failover_path).unwrap();
+    }
+
+    #[test]
+    fn rollover_open_errors_follow_the_policy() {
+        let mut block = block("rollover", json!({ "on_write_error": "drop", "rollover_duration_s": 1 }));
+        let directory = Path::new(&block.file_path).parent().unwrap().to_path_buf();
+        block.file_path = directory.join("out_$seq.rec").to_string_lossy().to_string();
+
+        // Opening the next file fails while a directory has its name
+        fs::create_dir_all(directory.join("out_0001.rec")).unwrap();
+
+        let mut writer = FileWriter::new(&block, &[]).unwrap();
+        writer.write(Some(&message(0))).unwrap();
+
+        let mut later = message(1);
+        later.wall_time += Duration::from_secs(2);
+        writer.write(Some(&later)).unwrap();
+        writer.write(Some(&message(2))).unwrap();
+        assert_eq!(writer.status.status.state, WriteState::Dropping);
+        assert_eq!(writer.status.status.dropped_records, 2);
+
+        fs::remove_dir(directory.join("out_0001.rec")).unwrap();
+        writer.write(Some(&message(3))).unwrap();
+        assert_eq!(writer.status.status.state, WriteState::Ok);
+        writer.finish().unwrap();
+
+        assert_eq!(payloads(directory.join("out_0000.rec").to_str().unwrap()), [0]);
+        assert_eq!(payloads(directory.join("out_0001.rec").to_str().unwrap()), [3]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn syncs() -> usize {
+        SYNCS.with(|syncs| syncs.get())
+    }
+
+    #[test]
+    fn flushes_when_the_buffer_fills_or_the_interval_passes() {
+        let block = block("flush", json!({ "flush_interval_ms": 300, "max_buffered_bytes": 250 }));
+        let mut writer = FileWriter::new(&block, &[]).unwrap();
+        writer.write(None).unwrap();
+
+        // Records of 126 bytes, the second one fills the buffer
+        writer.write(Some(&message(0))).unwrap();
+        assert!(payloads(&block.file_path).is_empty());
+        writer.write(Some(&message(1))).unwrap();
+        assert_eq!(payloads(&block.file_path), [0, 1]);
+
+        writer.write(Some(&message(2))).unwrap();
+        writer.write(None).unwrap();
+        assert_eq!(payloads(&block.file_path), [0, 1]);
+
+        thread::sleep(Duration::from_millis(300));
+        writer.write(None).unwrap();
+        assert_eq!(payloads(&block.file_path), [0, 1, 2]);
+
+        writer.finish().unwrap();
+        fs::remove_dir_all(Path::new(&block.file_path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn syncs_as_the_fsync_policy_says() {
+        for (policy, interval_syncs, rollover_syncs) in [("never", 0, 0), ("interval", 1, 1), ("rollover", 0, 1)] {
+            let mut block = block(
+                &format!("fsync-{}", policy),
+                json!({ "fsync": policy, "fsync_interval_ms": 100, "rollover_duration_s": 1 }),
+            );
+            let directory = Path::new(&block.file_path).parent().unwrap().to_path_buf();
+            block.file_path = directory.join("out_$seq.rec").to_string_lossy().to_string();
+
+            let mut writer = FileWriter::new(&block, &[]).unwrap();
+            let start = syncs();
+            writer.write(Some(&message(0))).unwrap();
+            assert_eq!(syncs() - start, 0, "{}", policy);
+
+            // A flush once the interval passed
+            thread::sleep(Duration::from_millis(100));
+            writer.write(Some(&message(1))).unwrap();
+            assert_eq!(syncs() - start, interval_syncs, "{}", policy);
+
+            // Closing the file on rollover
+            let mut later = message(2);
+            later.wall_time += Duration::from_secs(2);
+            writer.write(Some(&later)).unwrap();
+            assert_eq!(syncs() - start, interval_syncs + rollover_syncs, "{}", policy);
+
+            writer.finish().unwrap();
+            assert_eq!(payloads(directory.join("out_0000.rec").to_str().unwrap()), [0, 1]);
+            assert_eq!(payloads(directory.join("out_0001.rec").to_str().unwrap()), [2]);
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn carries_buffered_records_to_the_failover_file() {
+        let failover_path = env::temp_dir().join(format!("recorder-file-test-carry-to-{}", process::id()));
+        let _ = fs::remove_dir_all(&failover_path);
+        let block = block(
+            "carry",
+            json!({ "on_write_error": "failover", "failover_path": failover_path, "flush_interval_ms": 60000 }),
+        );
+
+        let mut writer = FileWriter::new(&block, &[]).unwrap();
+        writer.write(Some(&message(0))).unwrap();
+        writer.write(Some(&message(1))).unwrap();
+
+        // The flush of the third record fails after writing part of the first
+        let output = writer.output.as_mut().unwrap();
+        output.max_buffered_bytes = 0;
+        output.fail_after = Some(30);
+        writer.write(Some(&message(2))).unwrap();
+        assert_eq!(writer.status.status.state, WriteState::FailedOver);
+
+        writer.write(Some(&message(3))).unwrap();
+        writer.finish().unwrap();
+
+        assert!(payloads(&block.file_path).is_empty());
+        assert_eq!(payloads(failover_path.join("out.rec").to_str().unwrap()), [0, 1, 2, 3]);
+
+        fs::remove_dir_all(Path::new(&block.file_path).parent().unwrap()).unwrap();
+        fs::remove_dir_all(failover_path).unwrap();
     }
 }
@@ -0,0 +1,81 @@
+use std::{io::Error, net::SocketAddr};
+
+use crate::{
+    fixture::{read_fixture, FixturePacket},
+    format::Record,
+    recorder::{Block, BusWriter, Input},
+    replay::{replay, ReplaySource, Replayed},
+    template,
+};
+
+#[derive(Debug)]
+pub struct FixtureAdapter {}
+
+/// Fixture replayed by the fixture input, see `replay`
+struct FixtureSource {
+    path: String,
+    packets: Vec<FixturePacket>,
+    destination: Option<SocketAddr>,
+    /// Index of the next packet
+    position: usize,
+    /// Nanoseconds from the first packet to the one returned last
+    elapsed: u64,
+}
+
+impl ReplaySource for FixtureSource {
+    fn next(&mut self) -> Result<Option<Replayed>, Error> {
+        if self.position == self.packets.len() {
+            // Pick up packets appended since, a line still being written fails to parse until it is complete
+            match read_fixture(&self.path) {
+                Ok(packets) if packets.len() > self.packets.len() => self.packets = packets,
+                _ => return Ok(None),
+            }
+        }
+
+        // Replay starts with the first packet, so its delay does not count
+        let packet = &self.packets[self.position];
+        let delay = if self.position == 0 { 0 } else { packet.delay };
+        self.position += 1;
+        self.elapsed += delay;
+
+        Ok(Some(Replayed {
+            record: Record {
+                timestamp: 0,
+                monotonic: self.elapsed,
+                delta: delay,
+                elapsed: self.elapsed,
+                input: 0,
+                data: packet.payload.clone(),
+            },
+            source: None,
+            destination: self.destination,
+        }))
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.position = 0;
+        self.elapsed = 0;
+        Ok(())
+    }
+}
+
+/// Replays the text fixture in `file_path`, see `fixture` for its format.
+/// Packets carry `source_ip` and `source_port` as their destination if they are set.
+impl Input for FixtureAdapter {
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), Error> {
+        let file_path = template::input_path(&block.file_path, &block)?;
+
+        let packets = read_fixture(&file_path)?;
+        println!("Replaying fixture {} with {} packets", file_path, packets.len());
+
+        let mut source = FixtureSource {
+            path: file_path.clone(),
+            packets,
+            destination: block.source_address(),
+            position: 0,
+            elapsed: 0,
+        };
+
+        replay(&block, &file_path, &mut source, channel)
+    }
+}
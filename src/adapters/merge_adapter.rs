@@ -0,0 +1,95 @@
+use std::{
+    fs::File,
+    io::{BufReader, Error},
+};
+
+use crate::{
+    format::Corruption,
+    merge::Merger,
+    recorder::{Block, BusWriter, Input},
+    replay::{replay, ReplaySource, Replayed},
+    template,
+};
+
+#[derive(Debug)]
+pub struct MergeAdapter {}
+
+/// Merged recordings replayed by the merge input, see `replay`
+struct MergeSource {
+    paths: Vec<String>,
+    merger: Merger<BufReader<File>>,
+    corruption: Vec<Corruption>,
+    first: Option<u64>,
+    previous: Option<u64>,
+}
+
+impl ReplaySource for MergeSource {
+    fn next(&mut self) -> Result<Option<Replayed>, Error> {
+        if self.merger.peek_timestamp().is_none() {
+            self.merger.refill()?;
+        }
+
+        let next = self.merger.next_record()?;
+
+        let readers = self.merger.readers.iter();
+        for ((records, reported), path) in readers.zip(self.corruption.iter_mut()).zip(&self.paths) {
+            if records.corruption != *reported {
+                println!(
+                    "Skipped {} bytes of corrupt data in {}, resynchronised at offset {}",
+                    records.corruption.skipped_bytes - reported.skipped_bytes,
+                    path,
+                    records.offset()
+                );
+                *reported = records.corruption;
+            }
+        }
+
+        let Some((source, mut record)) = next else {
+            return Ok(None);
+        };
+
+        // Monotonic times of different recordings cannot be compared, pace by wall clock
+        let first = *self.first.get_or_insert(record.timestamp);
+        let previous = self.previous.replace(record.timestamp).unwrap_or(record.timestamp);
+        record.delta = record.timestamp.saturating_sub(previous);
+        record.elapsed = record.timestamp.saturating_sub(first);
+
+        Ok(Some(Replayed {
+            destination: self.merger.readers[source].header.input_address(record.input),
+            source: None,
+            record,
+        }))
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.first = None;
+        self.previous = None;
+        self.merger.rewind()
+    }
+}
+
+/// Replays the recordings in `file_paths` as one stream in capture time order.
+/// Every packet carries the group and port of the input which recorded it, see `Message.destination`.
+impl Input for MergeAdapter {
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), Error> {
+        let paths = block
+            .file_paths
+            .iter()
+            .map(|path| template::input_path(path, &block))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let merger = Merger::open(&paths)?;
+        println!("Replaying {} merged in capture time order", paths.join(", "));
+
+        let mut source = MergeSource {
+            corruption: merger.readers.iter().map(|records| records.corruption).collect(),
+            paths,
+            merger,
+            first: None,
+            previous: None,
+        };
+
+        let name = format!("merge of {} recordings", source.paths.len());
+        replay(&block, &name, &mut source, channel)
+    }
+}
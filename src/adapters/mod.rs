@@ -1,4 +1,7 @@
 pub mod file_adapter;
+pub mod fixture_adapter;
+pub mod merge_adapter;
+pub mod pcap_adapter;
 pub mod tcp_client_adapter;
 pub mod tcp_server_adapter;
 pub mod udp_adapter;
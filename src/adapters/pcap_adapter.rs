@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{BufReader, Error},
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use bus::BusReader;
+
+use crate::{
+    adapters::file_adapter::FileAdapter,
+    constants::BUF_SIZE,
+    format::Record,
+    message::Message,
+    pcap::CaptureReader,
+    recorder::{Block, BusWriter, Input, Output},
+    replay::{replay, ReplaySource, Replayed},
+    template,
+};
+
+#[derive(Debug)]
+pub struct PcapAdapter {}
+
+/// Writes a pcapng capture with the file output's rollover, retention, buffering and error handling.
+/// Packets keep their capture time, their source is the sender and their destination the input's group and port.
+impl Output for PcapAdapter {
+    fn write(&self, block: Block, inputs: &[Block], channel: &mut BusReader<Message>) -> Result<(), Error> {
+        FileAdapter {}.write(block, inputs, channel)
+    }
+}
+
+/// Capture replayed by the pcap input, see `replay`
+struct PcapSource {
+    path: String,
+    group: Ipv4Addr,
+    port: u16,
+    packets: CaptureReader<BufReader<File>>,
+    /// Reached the end, continue after the last complete packet to read packets appended since
+    ended: bool,
+    reported_skipped: u64,
+    first: Option<u64>,
+    previous: Option<u64>,
+}
+
+impl PcapSource {
+    fn open(path: &str) -> Result<CaptureReader<BufReader<File>>, Error> {
+        CaptureReader::new(BufReader::with_capacity(131072, File::open(path)?))
+    }
+}
+
+impl ReplaySource for PcapSource {
+    fn next(&mut self) -> Result<Option<Replayed>, Error> {
+        if self.ended {
+            self.packets.resume()?;
+            self.ended = false;
+        }
+
+        loop {
+            let Some(packet) = self.packets.next_packet()? else {
+                if self.packets.skipped != self.reported_skipped {
+                    println!("Skipped {} packets of {} which are not UDP over IPv4", self.packets.skipped, self.path);
+                    self.reported_skipped = self.packets.skipped;
+                }
+
+                self.ended = true;
+                return Ok(None);
+            };
+
+            if (!self.group.is_unspecified() && *packet.destination.ip() != self.group)
+                || (self.port != 0 && packet.destination.port() != self.port)
+            {
+                continue;
+            }
+
+            // Recordings cannot hold more, the UDP input truncates to it
+            if packet.payload.len() > BUF_SIZE {
+                println!(
+                    "Skipping packet of {} bytes in {}, larger than {} bytes",
+                    packet.payload.len(),
+                    self.path,
+                    BUF_SIZE
+                );
+                continue;
+            }
+
+            let first = *self.first.get_or_insert(packet.timestamp);
+            let previous = self.previous.replace(packet.timestamp).unwrap_or(packet.timestamp);
+            let elapsed = packet.timestamp.saturating_sub(first);
+
+            return Ok(Some(Replayed {
+                record: Record {
+                    timestamp: packet.timestamp,
+                    monotonic: elapsed,
+                    delta: packet.timestamp.saturating_sub(previous),
+                    elapsed,
+                    input: 0,
+                    data: packet.payload,
+                },
+                source: Some(SocketAddr::V4(packet.source)),
+                destination: Some(SocketAddr::V4(packet.destination)),
+            }));
+        }
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.packets = PcapSource::open(&self.path)?;
+        self.ended = false;
+        self.first = None;
+        self.previous = None;
+        Ok(())
+    }
+}
+
+/// Replays the UDP packets of a pcap or pcapng capture at `file_path`.
+/// A `source_ip` and `source_port` other than 0 only replay packets sent to that group and port.
+impl Input for PcapAdapter {
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), Error> {
+        let file_path = template::input_path(&block.file_path, &block)?;
+
+        let packets = PcapSource::open(&file_path)?;
+        println!("Replaying {:?} capture {}", packets.format, file_path);
+
+        let mut source = PcapSource {
+            path: file_path.clone(),
+            group: block.source_ip.parse().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            port: block.source_port,
+            packets,
+            ended: false,
+            reported_skipped: 0,
+            first: None,
+            previous: None,
+        };
+
+        replay(&block, &file_path, &mut source, channel)
+    }
+}
@@ -3,48 +3,44 @@ use std::{
     net::SocketAddrV4,
 };
 
-use bus::{Bus, BusReader};
+use bus::BusReader;
 use socket2::{Domain, Protocol, Socket, Type};
 
 use crate::{
     constants::BUF_SIZE,
-    recorder::{Block, Input, Output},
+    message::Message,
+    recorder::{Block, BusWriter, Input, Output},
+    utils::parse_ipv4,
 };
 
 #[derive(Debug)]
 pub struct TcpClientAdapter {}
 
 impl Input for TcpClientAdapter {
-    fn read(
-        &self,
-        block: Block,
-        channel: &mut Bus<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), std::io::Error> {
-        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), std::io::Error> {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
         // socket
         //     .bind(&SocketAddrV4::new(block.bind_ip.parse().unwrap(), block.bind_port).into())
         //     .unwrap();
-        socket
-            .connect(&SocketAddrV4::new(block.source_ip.parse().unwrap(), block.source_port).into())
-            .unwrap();
+        let address = SocketAddrV4::new(parse_ipv4("source_ip", &block.source_ip)?, block.source_port);
+        socket.connect(&address.into())?;
 
         let mut reader = BufReader::new(socket);
+        let mut buf = [0; BUF_SIZE];
 
         loop {
-            let mut buf = [0; BUF_SIZE];
-            let result = reader.read(&mut buf);
-            let length = result.as_ref().unwrap();
+            let length = reader.read(&mut buf)?;
 
-            if result.is_err() || *length == 0 {
+            if length == 0 {
                 break;
             }
 
             #[cfg(debug_assertions)]
             println!("Reading {:?} bytes from Tcp", length);
-            channel.broadcast((buf, *length as u32));
+            channel.send(&buf[..length], Some(address.into()));
         }
 
-        println!("Error while connecting");
+        println!("Connection to {} closed", address);
 
         Ok(())
     }
@@ -54,24 +50,21 @@ impl Output for TcpClientAdapter {
     fn write(
         &self,
         block: Block,
-        channel: &mut BusReader<([u8; BUF_SIZE], u32)>,
+        _inputs: &[Block],
+        channel: &mut BusReader<Message>,
     ) -> Result<(), std::io::Error> {
-        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
         // socket
         //     .bind(&SocketAddrV4::new(block.bind_ip.parse().unwrap(), block.bind_port).into())
         //     .unwrap();
-        socket
-            .connect(&SocketAddrV4::new(block.source_ip.parse().unwrap(), block.source_port).into())
-            .unwrap();
+        socket.connect(&SocketAddrV4::new(parse_ipv4("source_ip", &block.source_ip)?, block.source_port).into())?;
 
-        while let Ok((data, size)) = channel.recv() {
+        while let Ok(message) = channel.recv() {
             #[cfg(debug_assertions)]
-            println!("Writing {:?} bytes to tcp", size);
-            socket.send(&data).unwrap();
+            println!("Writing {:?} bytes to tcp", message.size());
+            socket.send(message.payload())?;
         }
 
-        println!("Error while connecting");
-
         Ok(())
     }
 }
@@ -1,10 +1,6 @@
 use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread};
-use bus::Bus;
 
-use crate::{
-    constants::BUF_SIZE,
-    recorder::{Block, Input},
-};
+use crate::recorder::{Block, BusWriter, Input};
 
 fn handle_conn(mut client_stream: TcpStream, source_ip: String, source_port: u16) -> std::io::Result<()> {
     // Connect to guthib (or the target server)
@@ -42,11 +38,7 @@ fn handle_conn(mut client_stream: TcpStream, source_ip: String, source_port: u16
 pub struct TcpProxyAdapter {}
 
 impl Input for TcpProxyAdapter {
-    fn read(
-        &self,
-        block: Block,
-        _channel: &mut Bus<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), std::io::Error> {
+    fn read(&self, block: Block, _channel: &BusWriter) -> Result<(), std::io::Error> {
         let listener = TcpListener::bind((block.clone().bind_ip, block.clone().bind_port))?;
 
         for client in listener.incoming() {
@@ -3,45 +3,42 @@ use std::{
     net::TcpListener,
 };
 
-use bus::{Bus, BusReader};
+use bus::BusReader;
 
 use crate::{
     constants::BUF_SIZE,
-    recorder::{Block, Input, Output},
+    message::Message,
+    recorder::{Block, BusWriter, Input, Output},
 };
 
 #[derive(Debug)]
 pub struct TcpServerAdapter {}
 
 impl Input for TcpServerAdapter {
-    fn read(
-        &self,
-        block: Block,
-        channel: &mut Bus<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), std::io::Error> {
-        let listener = TcpListener::bind((block.bind_ip, block.bind_port)).unwrap();
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), std::io::Error> {
+        let listener = TcpListener::bind((block.bind_ip, block.bind_port))?;
+        let mut buf = [0; BUF_SIZE];
 
-        while let Ok((conn, _)) = listener.accept() {
+        loop {
+            let (conn, address) = listener.accept()?;
             let mut reader = BufReader::new(conn);
 
+            // A failing connection ends only itself, the next one is accepted
             loop {
-                let mut buf = [0; BUF_SIZE];
-                let result = reader.read(&mut buf);
-                let length = result.as_ref().unwrap();
-
-                if result.is_err() || *length == 0 {
-                    break;
-                }
+                let length = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(length) => length,
+                    Err(error) => {
+                        println!("Connection from {} failed: {}", address, error);
+                        break;
+                    }
+                };
 
                 #[cfg(debug_assertions)]
                 println!("Reading {:?} bytes from Tcp", length);
-                channel.broadcast((buf, *length as u32));
+                channel.send(&buf[..length], Some(address));
             }
         }
-
-        println!("Error while connecting");
-
-        Ok(())
     }
 }
 
@@ -49,20 +46,22 @@ impl Output for TcpServerAdapter {
     fn write(
         &self,
         block: Block,
-        channel: &mut BusReader<([u8; BUF_SIZE], u32)>,
+        _inputs: &[Block],
+        channel: &mut BusReader<Message>,
     ) -> Result<(), std::io::Error> {
-        let listener = TcpListener::bind((block.bind_ip, block.bind_port)).unwrap();
+        let listener = TcpListener::bind((block.bind_ip, block.bind_port))?;
 
-        while let Ok((mut conn, _)) = listener.accept() {
-            if let Ok((data, size)) = channel.recv() {
-                #[cfg(debug_assertions)]
-                println!("Writing {:?} bytes to tcp", size);
-                conn.write(&data).unwrap();
+        loop {
+            let (mut conn, address) = listener.accept()?;
+            let Ok(message) = channel.recv() else {
+                return Ok(());
+            };
+
+            #[cfg(debug_assertions)]
+            println!("Writing {:?} bytes to tcp", message.size());
+            if let Err(error) = conn.write_all(message.payload()) {
+                println!("Connection from {} failed: {}", address, error);
             }
         }
-
-        println!("Error while connecting");
-
-        Ok(())
     }
 }
\ No newline at end of file
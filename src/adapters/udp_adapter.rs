@@ -1,83 +1,94 @@
-use std::{
-    io::{BufReader, Read}, net::{Ipv4Addr, SocketAddrV4}, os::fd::AsRawFd, str::FromStr
-};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
 
-use bus::{Bus, BusReader};
+use bus::BusReader;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 
 use crate::{
     constants::BUF_SIZE,
-    recorder::{Block, Input, Output},
+    message::Message,
+    recorder::{Block, BusWriter, Input, Output},
+    utils::parse_ipv4,
 };
 
 #[derive(Debug)]
 pub struct UdpAdapter {}
 
+/// Sends to `source_ip` and `source_port`. With `source_ip` 0.0.0.0 replayed packets are
+/// routed back to the group and port they were recorded from, others are dropped.
 impl Output for UdpAdapter {
     fn write(
         &self,
         block: Block,
-        channel: &mut BusReader<([u8; BUF_SIZE], u32)>,
+        _inputs: &[Block],
+        channel: &mut BusReader<Message>,
     ) -> Result<(), std::io::Error> {
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-        socket.set_reuse_address(true).unwrap();
-        socket
-            .join_multicast_v4(
-                &Ipv4Addr::from_str(&block.source_ip).unwrap(),
-                &Ipv4Addr::UNSPECIFIED,
-            )
-            .unwrap();
-        socket
-            .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())
-            .unwrap();
-
-        socket
-            .connect(&SockAddr::from(SocketAddrV4::new(
-                block.source_ip.parse().unwrap(),
-                block.source_port,
-            )))
-            .unwrap();
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
 
-        loop {
-            if let Ok((data, size)) = channel.recv() {
-                
-                #[cfg(debug_assertions)]
-                println!("Writing {:?} bytes to udp", size);
-                socket.send(&data[0..size as usize]).unwrap();
-                let x = socket.as_raw_fd();
-            }
+        let group = parse_ipv4("source_ip", &block.source_ip)?;
+        if group.is_unspecified() {
+            return UdpAdapter::route(socket, channel);
+        }
+
+        socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+        socket.connect(&SockAddr::from(SocketAddrV4::new(group, block.source_port)))?;
+
+        while let Ok(message) = channel.recv() {
+            #[cfg(debug_assertions)]
+            println!("Writing {:?} bytes to udp", message.size());
+            socket.send(message.payload())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl UdpAdapter {
+    fn route(socket: Socket, channel: &mut BusReader<Message>) -> Result<(), std::io::Error> {
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+        let mut unrouted = 0u64;
+
+        while let Ok(message) = channel.recv() {
+            let Some(destination) = message.destination else {
+                unrouted += 1;
+                if unrouted.is_power_of_two() {
+                    println!("Dropped {} packets without a recorded destination", unrouted);
+                }
+                continue;
+            };
+
+            #[cfg(debug_assertions)]
+            println!("Writing {:?} bytes to udp {}", message.size(), destination);
+            socket.send_to(message.payload(), &destination.into())?;
         }
+
+        Ok(())
     }
 }
 
 impl Input for UdpAdapter {
-    fn read(
-        &self,
-        block: Block,
-        channel: &mut Bus<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), std::io::Error> {
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-        socket.set_reuse_address(true).unwrap();
-        socket
-            .join_multicast_v4(&block.source_ip.parse().unwrap(), &Ipv4Addr::from_str(&block.interface_ip).unwrap())
-            .unwrap();
-        socket
-            .bind(&SocketAddrV4::new(Ipv4Addr::from_str(&block.bind_ip).unwrap(), block.source_port).into())
-            .unwrap();
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), std::io::Error> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.join_multicast_v4(
+            &parse_ipv4("source_ip", &block.source_ip)?,
+            &parse_ipv4("interface_ip", &block.interface_ip)?,
+        )?;
+        socket.bind(&SocketAddrV4::new(parse_ipv4("bind_ip", &block.bind_ip)?, block.source_port).into())?;
 
-        let mut reader = BufReader::new(socket);
+        // std socket gives us the sender address along with the datagram
+        let socket: UdpSocket = socket.into();
+        let mut buf = [0; BUF_SIZE];
 
         loop {
-            let mut buf = [0; BUF_SIZE];
-
-            let response = reader.read(&mut buf);
-            let length = response.as_ref().unwrap();
+            let (length, sender) = socket.recv_from(&mut buf)?;
 
-            if response.is_err() || *length == 0 {
+            if length == 0 {
                 break;
             }
 
-            channel.broadcast((buf, *length as u32));
+            channel.send(&buf[..length], Some(sender));
 
             #[cfg(debug_assertions)]
             println!("Reading {:?} bytes from udp", length);
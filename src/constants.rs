@@ -1 +1,3 @@
 pub const BUF_SIZE: usize = 32768;
+/// Messages the bus holds before inputs wait for outputs to catch up
+pub const BUS_SIZE: usize = 1000;
@@ -0,0 +1,189 @@
+//! Text fixtures, hand written packet sequences for testing consumers without real captures.
+//!
+//! Every line is a delay followed by a payload:
+//!
+//! ```text
+//! # blank lines and lines starting with # are skipped
+//! 0      "hello\n"
+//! 250ms  01 02 03 ff
+//! 1.5    "\x01\x02 mixed"
+//! ```
+//!
+//! The delay is waited before the packet, counted from the previous one, the first packet is sent right away.
+//! It is a number with unit `ns`, `us`, `ms`, `s`, `m` or `h`, seconds if there is none.
+//! A payload in double quotes is text with escapes `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\xNN`,
+//! any other payload is hex bytes, spaces between them allowed.
+
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+};
+
+use crate::{constants::BUF_SIZE, utils::parse_hex};
+
+#[derive(Debug, Clone)]
+pub struct FixturePacket {
+    /// Nanoseconds to wait before sending
+    pub delay: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Read and check a whole fixture, errors name the line
+pub fn read_fixture(path: &str) -> Result<Vec<FixturePacket>, Error> {
+    let text = fs::read_to_string(path)?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            parse_line(line.trim()).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} line {}: {}", path, i + 1, e)))
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<FixturePacket, String> {
+    let (delay, payload) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| "expected a delay and a payload".to_string())?;
+    let payload = payload.trim();
+
+    let payload = match payload.strip_prefix('"') {
+        Some(text) => unescape(text)?,
+        None => parse_hex(payload).map_err(|e| e.to_string())?,
+    };
+
+    if payload.len() > BUF_SIZE {
+        return Err(format!("payload of {} bytes is larger than {} bytes", payload.len(), BUF_SIZE));
+    }
+
+    Ok(FixturePacket {
+        delay: parse_delay(delay)?,
+        payload,
+    })
+}
+
+fn parse_delay(delay: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid delay {:?}", delay);
+
+    let split = delay.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(delay.len());
+    let (value, unit) = delay.split_at(split);
+    let value: f64 = value.parse().map_err(|_| invalid())?;
+
+    let nanos = match unit {
+        "ns" => 1.0,
+        "us" => 1e3,
+        "ms" => 1e6,
+        "" | "s" => 1e9,
+        "m" => 60e9,
+        "h" => 3600e9,
+        _ => return Err(invalid()),
+    };
+
+    if !value.is_finite() || value < 0.0 {
+        return Err(invalid());
+    }
+
+    Ok((value * nanos).round() as u64)
+}
+
+/// Bytes of quoted text, `text` starts after the opening quote and must end with the closing one
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+
+    loop {
+        match chars.next() {
+            None => return Err("missing closing quote".to_string()),
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => bytes.push(b'\n'),
+                Some('r') => bytes.push(b'\r'),
+                Some('t') => bytes.push(b'\t'),
+                Some('0') => bytes.push(0),
+                Some('\\') => bytes.push(b'\\'),
+                Some('"') => bytes.push(b'"'),
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    let byte = u8::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 2)
+                        .ok_or_else(|| format!("invalid escape \\x{}", hex))?;
+                    bytes.push(byte);
+                }
+                Some(c) => return Err(format!("invalid escape \\{}", c)),
+                None => return Err("missing closing quote".to_string()),
+            },
+            Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    if !chars.as_str().is_empty() {
+        return Err("unexpected text after closing quote".to_string());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn parses_text_and_hex_payloads() {
+        assert_eq!(parse_line("0 \"hello\\n\"").unwrap().payload, b"hello\n");
+        assert_eq!(parse_line("0 01 02 03 ff").unwrap().payload, [1, 2, 3, 0xff]);
+        assert_eq!(parse_line("0 0102ff").unwrap().payload, [1, 2, 0xff]);
+        assert_eq!(
+            parse_line(r#"0   "\x01\x02 \r\t\0\\\" é""#).unwrap().payload,
+            b"\x01\x02 \r\t\0\\\" \xc3\xa9"
+        );
+        assert_eq!(parse_line("0 \"\"").unwrap().payload, b"");
+
+        assert!(parse_line("0").is_err());
+        assert!(parse_line("0 \"open").is_err());
+        assert!(parse_line("0 \"a\" b").is_err());
+        assert!(parse_line("0 \"\\q\"").is_err());
+        assert!(parse_line("0 \"\\x1\"").is_err());
+        assert!(parse_line("0 0g").is_err());
+        assert!(parse_line(&format!("0 {}", "00".repeat(BUF_SIZE + 1))).is_err());
+    }
+
+    #[test]
+    fn parses_delays_with_units() {
+        assert_eq!(parse_delay("0").unwrap(), 0);
+        assert_eq!(parse_delay("1.5").unwrap(), 1_500_000_000);
+        assert_eq!(parse_delay("2s").unwrap(), 2_000_000_000);
+        assert_eq!(parse_delay("250ms").unwrap(), 250_000_000);
+        assert_eq!(parse_delay("3us").unwrap(), 3_000);
+        assert_eq!(parse_delay("7ns").unwrap(), 7);
+        assert_eq!(parse_delay("2m").unwrap(), 120_000_000_000);
+        assert_eq!(parse_delay("1h").unwrap(), 3_600_000_000_000);
+
+        assert!(parse_delay("").is_err());
+        assert!(parse_delay("ms").is_err());
+        assert!(parse_delay("-1").is_err());
+        assert!(parse_delay("1d").is_err());
+        assert!(parse_delay("inf").is_err());
+    }
+
+    #[test]
+    fn reads_fixtures_skipping_comments_and_naming_bad_lines() {
+        let path = env::temp_dir().join(format!("recorder-fixture-test-{}", process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::write(path, "# header\n\n0 \"a\"\n  # indented comment\n10ms 62\n").unwrap();
+        let packets = read_fixture(path).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!((packets[0].delay, packets[0].payload.as_slice()), (0, &b"a"[..]));
+        assert_eq!((packets[1].delay, packets[1].payload.as_slice()), (10_000_000, &b"b"[..]));
+
+        fs::write(path, "0 \"a\"\n\n1x 62\n").unwrap();
+        let error = read_fixture(path).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 3"), "{}", error);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1279 @@
+//! Recording file format shared by the file adapter and the offline tools.
+//!
+//! A recording starts with a file header
+//! `[magic: "RECORDER"][version: u16][header length: u32][header: json]`
+//! followed by records, all integers big endian.
+//!
+//! Version 4 files with `compression` set in the header store records in compressed blocks
+//! `[magic: "RBLK"][size: u32][compressed size: u32][crc: u32][compressed records]`.
+//! A block holds whole records, its CRC-32 covers the compressed records. Index entries
+//! of compressed recordings point at the block of the record and only exist for records
+//! starting a block. Uncompressed version 4 files are the same as version 3 files.
+//!
+//! Version 3 records are
+//! `[timestamp ns: u64][monotonic ns: u64][input: u16][size: u32][crc: u32][payload]`.
+//! Timestamp is the wall clock capture time since unix epoch, monotonic is the capture time
+//! on a monotonic clock relative to when the writer opened the file, and input is the index
+//! of the producing block in the header inputs. The CRC-32 covers the rest of the record
+//! header and the payload, it is only present if the file header has `checksums` set.
+//!
+//! Version 2 records are version 3 records without a CRC.
+//! Version 1 records are `[time diff ms: u32][size: u32][payload]`.
+//! Legacy recordings have no file header and start directly with version 1 records,
+//! they are reported as version 0.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::BUF_SIZE,
+    index::{self, IndexEntry},
+    recorder::Block,
+    utils::{bytes_to_u32, hostname, u32_to_bytes},
+};
+
+pub const MAGIC: [u8; 8] = *b"RECORDER";
+pub const FORMAT_VERSION: u16 = 4;
+pub const LEGACY_VERSION: u16 = 0;
+
+/// Size of magic, version and header length
+const PREAMBLE_SIZE: u64 = 14;
+
+/// Largest record header, version 3 with checksums
+pub const MAX_RECORD_HEADER_SIZE: usize = 26;
+pub const BLOCK_MAGIC: [u8; 4] = *b"RBLK";
+const BLOCK_HEADER_SIZE: usize = 16;
+/// Largest uncompressed size of a block
+pub const MAX_BLOCK_SIZE: usize = 16 << 20;
+/// Buffered records are written as a block once they reach this size,
+/// so a block with one more record stays below `MAX_BLOCK_SIZE`
+pub const MAX_BLOCK_FILL: usize = MAX_BLOCK_SIZE - BUF_SIZE - MAX_RECORD_HEADER_SIZE;
+/// Records without checksums claiming to be captured this long before the file was created
+/// or after now are taken as corrupt, as are time diffs larger than this
+const MAX_TIMESTAMP_SKEW: u64 = 24 * 3600 * 1_000_000_000;
+/// How much of the file is read at once while looking for the next valid record
+const RESYNC_CHUNK: usize = 1 << 20;
+
+/// Codec of compressed recordings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    /// Compress a block, `level` only applies to zstd where 0 is its default level
+    pub fn compress(self, data: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zstd => zstd::bulk::compress(data, level),
+            Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+        }
+    }
+
+    /// Decompress a block of `size` bytes
+    pub fn decompress(self, data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+        let decompressed = match self {
+            Compression::None => data.to_vec(),
+            Compression::Zstd => zstd::bulk::decompress(data, size)?,
+            Compression::Lz4 => {
+                lz4_flex::block::decompress(data, size).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            }
+        };
+
+        if decompressed.len() != size {
+            return Err(Error::new(ErrorKind::InvalidData, "block size does not match its header"));
+        }
+
+        Ok(decompressed)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression, Error> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("unknown compression {:?}", s)))
+    }
+}
+
+/// Compress records into a block ready to be written
+pub fn encode_block(compression: Compression, level: i32, records: &[u8]) -> Result<Vec<u8>, Error> {
+    let compressed = compression.compress(records, level)?;
+
+    let mut block = Vec::with_capacity(BLOCK_HEADER_SIZE + compressed.len());
+    block.extend_from_slice(&BLOCK_MAGIC);
+    block.extend_from_slice(&u32_to_bytes(records.len() as u32));
+    block.extend_from_slice(&u32_to_bytes(compressed.len() as u32));
+    block.extend_from_slice(&u32_to_bytes(crc32fast::hash(&compressed)));
+    block.extend_from_slice(&compressed);
+
+    Ok(block)
+}
+
+/// Sizes and CRC of a block, None if the header cannot belong to a valid block
+fn decode_block_header(header: &[u8; BLOCK_HEADER_SIZE]) -> Option<(usize, usize, u32)> {
+    let field = |from: usize| bytes_to_u32(header[from..from + 4].try_into().unwrap());
+    let (size, compressed_size) = (field(4) as usize, field(8) as usize);
+
+    if header[..4] != BLOCK_MAGIC || size > MAX_BLOCK_SIZE || compressed_size > 2 * MAX_BLOCK_SIZE {
+        return None;
+    }
+
+    Some((size, compressed_size, field(12)))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileHeader {
+    /// Format version, taken from the preamble
+    #[serde(skip)]
+    pub version: u16,
+    /// Offset of the first record
+    #[serde(skip)]
+    pub data_offset: u64,
+    pub created_at: String,
+    pub host: String,
+    pub recorder_version: String,
+    /// Input blocks which produced the data
+    pub inputs: Vec<Block>,
+    /// Records carry a CRC-32
+    #[serde(default)]
+    pub checksums: bool,
+    /// Records are stored in compressed blocks
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+impl FileHeader {
+    pub fn new(inputs: Vec<Block>) -> FileHeader {
+        FileHeader {
+            version: FORMAT_VERSION,
+            data_offset: 0,
+            created_at: Local::now().to_rfc3339(),
+            host: hostname(),
+            recorder_version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs,
+            checksums: false,
+            compression: Compression::None,
+        }
+    }
+
+    /// Header reported for files without one
+    pub fn legacy() -> FileHeader {
+        FileHeader {
+            version: LEGACY_VERSION,
+            data_offset: 0,
+            created_at: String::new(),
+            host: String::new(),
+            recorder_version: String::new(),
+            inputs: vec![],
+            checksums: false,
+            compression: Compression::None,
+        }
+    }
+
+    /// Group or host and port an input of the recording read from, None if it has no address
+    pub fn input_address(&self, input: u16) -> Option<SocketAddr> {
+        self.inputs.get(input as usize)?.source_address()
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY_VERSION
+    }
+
+    /// Size of the headers in front of every payload
+    pub fn record_header_size(&self) -> u64 {
+        match self.version {
+            0 | 1 => 8,
+            2 => 22,
+            _ if self.checksums => 26,
+            _ => 22,
+        }
+    }
+
+    /// Creation time in nanoseconds since unix epoch
+    pub fn created_at_nanos(&self) -> Option<u64> {
+        DateTime::parse_from_rfc3339(&self.created_at)
+            .ok()
+            .and_then(|created| created.timestamp_nanos_opt())
+            .map(|nanos| nanos as u64)
+    }
+
+    /// Write the header at the current position, records follow right after it
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> Result<(), Error> {
+        let json = serde_json::to_vec(self)?;
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&self.version.to_be_bytes())?;
+        writer.write_all(&u32_to_bytes(json.len() as u32))?;
+        writer.write_all(&json)?;
+
+        self.data_offset = PREAMBLE_SIZE + json.len() as u64;
+
+        Ok(())
+    }
+
+    /// Read the header from the start of a recording and leave the reader at the first record.
+    /// Files without magic are accepted as legacy recordings if their first record looks sane.
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> Result<FileHeader, Error> {
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut preamble = [0; PREAMBLE_SIZE as usize];
+        let read = read_full(reader, &mut preamble)?;
+
+        if read < MAGIC.len() || preamble[..MAGIC.len()] != MAGIC {
+            reader.seek(SeekFrom::Start(0))?;
+
+            return match read {
+                0 => Ok(FileHeader::legacy()),
+                _ if read as u64 >= FileHeader::legacy().record_header_size()
+                    && (bytes_to_u32(preamble[4..8].try_into().unwrap()) as usize) <= BUF_SIZE =>
+                {
+                    Ok(FileHeader::legacy())
+                }
+                _ => Err(Error::new(ErrorKind::InvalidData, "not a recording, missing magic and record headers")),
+            };
+        }
+
+        if read < PREAMBLE_SIZE as usize {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated file header"));
+        }
+
+        let version = u16::from_be_bytes([preamble[8], preamble[9]]);
+
+        if version > FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("recording format version {} is newer than supported version {}", version, FORMAT_VERSION),
+            ));
+        }
+
+        let length = bytes_to_u32(preamble[10..14].try_into().unwrap());
+        let mut json = vec![0; length as usize];
+        reader.read_exact(&mut json)?;
+
+        let mut header: FileHeader = serde_json::from_slice(&json)?;
+        header.version = version;
+        header.data_offset = PREAMBLE_SIZE + length as u64;
+
+        Ok(header)
+    }
+
+    /// Read the header of the recording at `path`
+    pub fn read_path<P: AsRef<Path>>(path: P) -> Result<FileHeader, Error> {
+        FileHeader::read_from(&mut fs::File::open(path)?)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Record {
+    /// Wall clock capture time in nanoseconds since unix epoch.
+    /// Version 1 derives it from the header creation time, legacy files report 0.
+    pub timestamp: u64,
+    /// Monotonic capture time in nanoseconds.
+    /// Formats with time diffs report the sum of all diffs since the first record.
+    pub monotonic: u64,
+    /// Nanoseconds since the previous record, filled in by the reader
+    pub delta: u64,
+    /// Nanoseconds since the first record, filled in by the reader
+    pub elapsed: u64,
+    /// Index of the producing block in the header inputs
+    pub input: u16,
+    pub data: Vec<u8>,
+}
+
+/// Writes records in the current format version, with checksums if the file header asks for them
+#[derive(Debug)]
+pub struct RecordWriter<W> {
+    writer: W,
+    checksums: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Writer for records following `header`, which must already be written
+    pub fn new(writer: W, header: &FileHeader) -> RecordWriter<W> {
+        RecordWriter {
+            writer,
+            checksums: header.checksums,
+        }
+    }
+
+    pub fn write(&mut self, timestamp: u64, monotonic: u64, input: u16, data: &[u8]) -> Result<(), Error> {
+        let mut header = [0; MAX_RECORD_HEADER_SIZE];
+        header[0..8].copy_from_slice(&timestamp.to_be_bytes());
+        header[8..16].copy_from_slice(&monotonic.to_be_bytes());
+        header[16..18].copy_from_slice(&input.to_be_bytes());
+        header[18..22].copy_from_slice(&u32_to_bytes(data.len() as u32));
+
+        let header = if self.checksums {
+            let crc = checksum(&header[..22], data);
+            header[22..26].copy_from_slice(&u32_to_bytes(crc));
+            &header[..26]
+        } else {
+            &header[..22]
+        };
+
+        self.writer.write_all(header)?;
+        self.writer.write_all(data)
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.write(record.timestamp, record.monotonic, record.input, &record.data)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+/// CRC-32 of a record header without its CRC field, followed by the payload
+fn checksum(header: &[u8], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Writes a whole recording file, framing records into blocks of `RECORDING_BLOCK_SIZE`
+/// if the header asks for compression
+#[derive(Debug)]
+pub struct RecordingWriter<W: Write> {
+    writer: W,
+    records: RecordWriter<Vec<u8>>,
+    compression: Compression,
+    /// Bytes of records written so far, before compression
+    pub size: u64,
+}
+
+/// Uncompressed size of the blocks `RecordingWriter` writes
+pub const RECORDING_BLOCK_SIZE: usize = 1 << 20;
+
+impl<W: Write> RecordingWriter<W> {
+    /// Write the file header, records follow it
+    pub fn create(mut writer: W, header: &mut FileHeader) -> Result<RecordingWriter<W>, Error> {
+        header.write_to(&mut writer)?;
+
+        Ok(RecordingWriter {
+            writer,
+            records: RecordWriter::new(Vec::with_capacity(RECORDING_BLOCK_SIZE), header),
+            compression: header.compression,
+            size: 0,
+        })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        let buffered = self.records.get_ref().len();
+        self.records.write_record(record)?;
+        self.size += (self.records.get_ref().len() - buffered) as u64;
+
+        if self.records.get_ref().len() >= RECORDING_BLOCK_SIZE {
+            self.write_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write whatever is buffered and flush
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_buffer()?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    fn write_buffer(&mut self) -> Result<(), Error> {
+        let records = self.records.get_mut();
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        match self.compression {
+            Compression::None => self.writer.write_all(records)?,
+            compression => self.writer.write_all(&encode_block(compression, 0, records)?)?,
+        }
+
+        records.clear();
+        Ok(())
+    }
+}
+
+/// Nanoseconds between two version 2 records.
+/// Monotonic clock restarts when a recording is appended to, fall back to wall clock then.
+pub fn record_delta(prev_timestamp: u64, prev_monotonic: u64, timestamp: u64, monotonic: u64) -> u64 {
+    if monotonic >= prev_monotonic {
+        monotonic - prev_monotonic
+    } else {
+        timestamp.saturating_sub(prev_timestamp)
+    }
+}
+
+/// Data skipped by the reader because it did not form valid records
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Corruption {
+    /// Number of places where the reader had to look for the next valid record
+    pub bad_records: u64,
+    pub skipped_bytes: u64,
+}
+
+/// Record header fields as stored, before timing is filled in
+#[derive(Debug)]
+struct RawHeader {
+    timestamp: u64,
+    monotonic: u64,
+    diff: u64,
+    input: u16,
+    size: usize,
+    crc: Option<u32>,
+}
+
+/// Outcome of decoding a record from bytes
+enum Decoded {
+    Valid,
+    /// Not enough bytes to tell
+    Incomplete,
+    Invalid,
+}
+
+/// Sequential record reader which understands every supported format version.
+/// Corrupt data is skipped by looking for the next valid record, see `corruption`.
+pub struct RecordReader<R> {
+    reader: R,
+    pub header: FileHeader,
+    /// Offset of the next record
+    offset: u64,
+    /// Number of the next record, counted from 0
+    position: u64,
+    /// Timestamps of the previous record, used for deltas
+    prev_timestamp: u64,
+    prev_monotonic: u64,
+    /// Sum of deltas since the first record
+    elapsed: Option<u64>,
+    /// Creation time, base of version 1 timestamps
+    created_at: u64,
+    /// Index entry of the next record after a seek
+    resume: Option<IndexEntry>,
+    /// Where the last record starts if an index entry can point at it
+    record_offset: Option<u64>,
+    /// Decompressed records of the current block of a compressed recording
+    block: Vec<u8>,
+    /// Position of the next record in `block`
+    block_position: usize,
+    /// Offset of the current block, `offset` is past it
+    block_offset: u64,
+    /// Data skipped so far
+    pub corruption: Corruption,
+}
+
+impl<R: Read + Seek> RecordReader<R> {
+    pub fn new(mut reader: R) -> Result<RecordReader<R>, Error> {
+        let header = FileHeader::read_from(&mut reader)?;
+
+        Ok(RecordReader {
+            reader,
+            offset: header.data_offset,
+            position: 0,
+            created_at: header.created_at_nanos().unwrap_or_default(),
+            header,
+            prev_timestamp: 0,
+            prev_monotonic: 0,
+            elapsed: None,
+            resume: None,
+            record_offset: None,
+            block: vec![],
+            block_position: 0,
+            block_offset: 0,
+            corruption: Corruption::default(),
+        })
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.header.compression != Compression::None
+    }
+
+    /// Offset of the next record, or of its block in compressed recordings
+    pub fn offset(&self) -> u64 {
+        if self.block_position < self.block.len() {
+            self.block_offset
+        } else {
+            self.offset
+        }
+    }
+
+    /// Offset an index entry for the last record read would have,
+    /// None if the record is in the middle of a compressed block
+    pub fn record_offset(&self) -> Option<u64> {
+        self.record_offset
+    }
+
+    /// Number of the next record, counted from 0
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Go back to the first record
+    pub fn rewind(&mut self) -> Result<(), Error> {
+        self.offset = self.header.data_offset;
+        self.position = 0;
+        self.prev_timestamp = 0;
+        self.prev_monotonic = 0;
+        self.elapsed = None;
+        self.resume = None;
+        self.block.clear();
+        self.reader.seek(SeekFrom::Start(self.offset))?;
+
+        Ok(())
+    }
+
+    /// Jump to the record described by an index entry.
+    /// The next record reports the entry's times and a delta of 0, as the previous record is unknown.
+    pub fn seek_to(&mut self, entry: &IndexEntry) -> Result<(), Error> {
+        self.offset = entry.offset;
+        self.position = entry.record;
+        self.elapsed = Some(entry.elapsed);
+        self.resume = Some(*entry);
+        self.block.clear();
+        self.reader.seek(SeekFrom::Start(self.offset))?;
+
+        Ok(())
+    }
+
+    /// Read the next record.
+    /// Returns None at the end of the file, a partially written record is left in place
+    /// so it can be read again once the writer completes it.
+    pub fn next_record(&mut self) -> Result<Option<Record>, Error> {
+        if self.is_compressed() {
+            return self.next_block_record();
+        }
+
+        loop {
+            let header_size = self.header.record_header_size() as usize;
+            let mut header = [0; MAX_RECORD_HEADER_SIZE];
+            let header = &mut header[..header_size];
+
+            if read_full(&mut self.reader, header)? < header_size {
+                return self.partial();
+            }
+
+            let raw = match self.decode_header(header, false) {
+                Some(raw) => raw,
+                None => {
+                    self.resync()?;
+                    continue;
+                }
+            };
+
+            let mut data = vec![0; raw.size];
+            if read_full(&mut self.reader, &mut data)? < raw.size {
+                return self.partial();
+            }
+
+            if raw.crc.is_some_and(|crc| crc != checksum(&header[..22], &data)) {
+                self.resync()?;
+                continue;
+            }
+
+            self.record_offset = Some(self.offset);
+            self.offset += (header_size + raw.size) as u64;
+
+            return self.finish(raw, data).map(Some);
+        }
+    }
+
+    fn next_block_record(&mut self) -> Result<Option<Record>, Error> {
+        loop {
+            if self.block_position < self.block.len() {
+                let header_size = self.header.record_header_size() as usize;
+                let rest = &self.block[self.block_position..];
+
+                // The block CRC makes corrupt records unlikely, drop the rest of the block if there are any
+                let record = rest
+                    .get(..header_size)
+                    .and_then(|header| self.decode_header(header, false))
+                    .filter(|raw| rest.len() >= header_size + raw.size)
+                    .filter(|raw| {
+                        raw.crc
+                            .is_none_or(|crc| crc == checksum(&rest[..22], &rest[header_size..header_size + raw.size]))
+                    });
+
+                let Some(raw) = record else {
+                    self.corruption.bad_records += 1;
+                    self.corruption.skipped_bytes += rest.len() as u64;
+                    self.block.clear();
+                    continue;
+                };
+
+                let data = rest[header_size..header_size + raw.size].to_vec();
+                self.record_offset = (self.block_position == 0).then_some(self.block_offset);
+                self.block_position += header_size + raw.size;
+
+                return self.finish(raw, data).map(Some);
+            }
+
+            if !self.read_block()? {
+                return self.partial();
+            }
+        }
+    }
+
+    /// Load the block at `offset` and move `offset` past it.
+    /// Returns false if the block is not completely written yet.
+    fn read_block(&mut self) -> Result<bool, Error> {
+        loop {
+            self.reader.seek(SeekFrom::Start(self.offset))?;
+
+            let mut header = [0; BLOCK_HEADER_SIZE];
+            if read_full(&mut self.reader, &mut header)? < BLOCK_HEADER_SIZE {
+                return Ok(false);
+            }
+
+            let Some((size, compressed_size, crc)) = decode_block_header(&header) else {
+                self.resync_block()?;
+                continue;
+            };
+
+            let mut compressed = vec![0; compressed_size];
+            if read_full(&mut self.reader, &mut compressed)? < compressed_size {
+                return Ok(false);
+            }
+
+            let block = match crc32fast::hash(&compressed) == crc {
+                true => self.header.compression.decompress(&compressed, size),
+                false => Err(Error::new(ErrorKind::InvalidData, "block CRC mismatch")),
+            };
+
+            let Ok(block) = block else {
+                self.resync_block()?;
+                continue;
+            };
+
+            self.block = block;
+            self.block_position = 0;
+            self.block_offset = self.offset;
+            self.offset += (BLOCK_HEADER_SIZE + compressed_size) as u64;
+
+            return Ok(true);
+        }
+    }
+
+    /// Move to the next offset where a valid block starts, or to the end of the file.
+    /// A block cut off by the end of the file counts as valid, it may still be written.
+    fn resync_block(&mut self) -> Result<(), Error> {
+        let bad_offset = self.offset;
+
+        let mut start = bad_offset + 1;
+        let found = 'scan: loop {
+            self.reader.seek(SeekFrom::Start(start))?;
+
+            let mut chunk = vec![0; RESYNC_CHUNK];
+            let read = read_full(&mut self.reader, &mut chunk)?;
+
+            for i in 0..read.saturating_sub(BLOCK_MAGIC.len() - 1) {
+                if chunk[i..].starts_with(&BLOCK_MAGIC) && self.block_at(start + i as u64)? {
+                    break 'scan start + i as u64;
+                }
+            }
+
+            if read < RESYNC_CHUNK {
+                break start + read as u64;
+            }
+
+            // Keep the last bytes, a magic may straddle chunks
+            start += (read - (BLOCK_MAGIC.len() - 1)) as u64;
+        };
+
+        self.corruption.bad_records += 1;
+        self.corruption.skipped_bytes += found - bad_offset;
+        self.offset = found;
+        self.reader.seek(SeekFrom::Start(found))?;
+
+        Ok(())
+    }
+
+    /// Whether a valid or not yet completely written block starts at an offset
+    fn block_at(&mut self, offset: u64) -> Result<bool, Error> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        let mut header = [0; BLOCK_HEADER_SIZE];
+        if read_full(&mut self.reader, &mut header)? < BLOCK_HEADER_SIZE {
+            return Ok(true);
+        }
+
+        let Some((_, compressed_size, crc)) = decode_block_header(&header) else {
+            return Ok(false);
+        };
+
+        let mut compressed = vec![0; compressed_size];
+        if read_full(&mut self.reader, &mut compressed)? < compressed_size {
+            return Ok(true);
+        }
+
+        Ok(crc32fast::hash(&compressed) == crc)
+    }
+
+    /// Fill in timing of a valid record the reader moved past
+    fn finish(&mut self, raw: RawHeader, data: Vec<u8>) -> Result<Record, Error> {
+        let mut record = Record {
+            timestamp: raw.timestamp,
+            monotonic: raw.monotonic,
+            input: raw.input,
+            data,
+            ..Default::default()
+        };
+
+        if self.header.version < 2 {
+            record.delta = raw.diff;
+            record.monotonic = self.prev_monotonic + raw.diff;
+
+            if !self.header.is_legacy() {
+                record.timestamp = self.created_at + record.monotonic;
+            }
+        } else {
+            record.delta = record_delta(self.prev_timestamp, self.prev_monotonic, record.timestamp, record.monotonic);
+        }
+
+        if let Some(entry) = self.resume.take() {
+            if entry.timestamp != record.timestamp && self.header.version >= 2 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("index entry for record {} does not match the recording", entry.record),
+                ));
+            }
+
+            record.monotonic = entry.monotonic;
+            record.timestamp = entry.timestamp;
+            record.delta = 0;
+        }
+
+        record.elapsed = self.elapsed.map_or(0, |elapsed| elapsed + record.delta);
+
+        self.position += 1;
+        self.prev_timestamp = record.timestamp;
+        self.prev_monotonic = record.monotonic;
+        self.elapsed = Some(record.elapsed);
+
+        Ok(record)
+    }
+
+    /// Decode a record header, None if it cannot belong to a valid record.
+    /// Headers with a checksum are only checked for plausible times and inputs when `strict`,
+    /// as the checksum verifies them once the payload is read.
+    fn decode_header(&self, header: &[u8], strict: bool) -> Option<RawHeader> {
+        let field = |from: usize, to: usize| &header[from..to];
+
+        let raw = if self.header.version < 2 {
+            RawHeader {
+                timestamp: 0,
+                monotonic: 0,
+                diff: bytes_to_u32(field(0, 4).try_into().unwrap()) as u64 * 1_000_000,
+                input: 0,
+                size: bytes_to_u32(field(4, 8).try_into().unwrap()) as usize,
+                crc: None,
+            }
+        } else {
+            RawHeader {
+                timestamp: u64::from_be_bytes(field(0, 8).try_into().unwrap()),
+                monotonic: u64::from_be_bytes(field(8, 16).try_into().unwrap()),
+                diff: 0,
+                input: u16::from_be_bytes(field(16, 18).try_into().unwrap()),
+                size: bytes_to_u32(field(18, 22).try_into().unwrap()) as usize,
+                crc: (header.len() == 26).then(|| bytes_to_u32(field(22, 26).try_into().unwrap())),
+            }
+        };
+
+        if raw.size > BUF_SIZE || raw.diff > MAX_TIMESTAMP_SKEW {
+            return None;
+        }
+
+        if self.header.version >= 2 && (strict || raw.crc.is_none()) {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+
+            if raw.input as usize >= self.header.inputs.len().max(1)
+                || raw.timestamp < self.created_at.saturating_sub(MAX_TIMESTAMP_SKEW)
+                || raw.timestamp > now + MAX_TIMESTAMP_SKEW
+            {
+                return None;
+            }
+        }
+
+        Some(raw)
+    }
+
+    /// Check whether a valid record starts at the beginning of `bytes`.
+    /// Without checksums the record following it must look valid as well.
+    fn decode_at(&self, bytes: &[u8], at_eof: bool) -> Decoded {
+        let header_size = self.header.record_header_size() as usize;
+
+        if bytes.len() < header_size {
+            return if at_eof { Decoded::Incomplete } else { Decoded::Invalid };
+        }
+
+        let Some(raw) = self.decode_header(&bytes[..header_size], true) else {
+            return Decoded::Invalid;
+        };
+
+        let end = header_size + raw.size;
+        if bytes.len() < end {
+            return Decoded::Incomplete;
+        }
+
+        match raw.crc {
+            Some(crc) if crc == checksum(&bytes[..22], &bytes[header_size..end]) => Decoded::Valid,
+            Some(_) => Decoded::Invalid,
+            None if bytes.len() == end && at_eof => Decoded::Valid,
+            None => match bytes.get(end..end + header_size) {
+                Some(next) if self.decode_header(next, true).is_some() => Decoded::Valid,
+                Some(_) => Decoded::Invalid,
+                None => Decoded::Incomplete,
+            },
+        }
+    }
+
+    /// Move to the next offset where a valid record starts, or to the end of the file.
+    /// A record cut off by the end of the file counts as valid, it may still be written.
+    fn resync(&mut self) -> Result<(), Error> {
+        let bad_offset = self.offset;
+        let max_record = MAX_RECORD_HEADER_SIZE * 2 + BUF_SIZE;
+
+        let mut start = bad_offset + 1;
+        let found = 'scan: loop {
+            self.reader.seek(SeekFrom::Start(start))?;
+
+            let mut chunk = vec![0; RESYNC_CHUNK + max_record];
+            let read = read_full(&mut self.reader, &mut chunk)?;
+            chunk.truncate(read);
+            let at_eof = read < RESYNC_CHUNK + max_record;
+
+            // Every candidate in the first part of the chunk has a whole record after it
+            let candidates = if at_eof { read } else { RESYNC_CHUNK };
+
+            for i in 0..candidates {
+                match self.decode_at(&chunk[i..], at_eof) {
+                    Decoded::Valid | Decoded::Incomplete => break 'scan start + i as u64,
+                    Decoded::Invalid => {}
+                }
+            }
+
+            if at_eof {
+                break start + read as u64;
+            }
+
+            start += candidates as u64;
+        };
+
+        self.corruption.bad_records += 1;
+        self.corruption.skipped_bytes += found - bad_offset;
+        self.offset = found;
+        self.reader.seek(SeekFrom::Start(found))?;
+
+        Ok(())
+    }
+
+    fn partial(&mut self) -> Result<Option<Record>, Error> {
+        self.reader.seek(SeekFrom::Start(self.offset))?;
+
+        Ok(None)
+    }
+}
+
+/// Read until `buf` is full or the end of file is reached, returns number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+/// What to do with a partially written record at the end of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailRepair {
+    /// Cut it off
+    Truncate,
+    /// Move its bytes to `<recording>.<offset>.partial` before cutting it off
+    Quarantine,
+}
+
+/// Path partial record bytes starting at `offset` are quarantined to
+pub fn quarantine_path(recording: &str, offset: u64) -> String {
+    format!("{}.{}.partial", recording, offset)
+}
+
+/// Partial record removed from the end of a recording
+#[derive(Debug, Clone, Copy)]
+pub struct RemovedTail {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Remove the partial record a killed writer left at the end of a recording,
+/// so records appended later are not read as part of it.
+pub fn repair_tail(recording: &str, repair: TailRepair) -> Result<Option<RemovedTail>, Error> {
+    let mut records = RecordReader::new(BufReader::new(fs::File::open(recording)?))?;
+    let mut end = records.offset();
+
+    // Corrupt bytes with no valid record after them are the torn record as well
+    while records.next_record()?.is_some() {
+        end = records.offset();
+    }
+
+    let length = fs::metadata(recording)?.len();
+    if end >= length {
+        return Ok(None);
+    }
+
+    if repair == TailRepair::Quarantine {
+        let mut tail = vec![];
+        let mut file = fs::File::open(recording)?;
+        file.seek(SeekFrom::Start(end))?;
+        file.read_to_end(&mut tail)?;
+
+        fs::write(quarantine_path(recording, end), tail)?;
+    }
+
+    OpenOptions::new().write(true).open(recording)?.set_len(end)?;
+    index::trim(recording, end)?;
+
+    Ok(Some(RemovedTail {
+        offset: end,
+        length: length - end,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// File header of the given version, followed by nothing yet
+    fn recording(version: u16, checksums: bool, compression: Compression) -> (FileHeader, Vec<u8>) {
+        let mut header = FileHeader::new(vec![]);
+        header.version = version;
+        header.checksums = checksums;
+        header.compression = compression;
+
+        let mut file = vec![];
+        header.write_to(&mut file).unwrap();
+
+        (header, file)
+    }
+
+    /// Version 1 and legacy record
+    fn v1_record(file: &mut Vec<u8>, diff_ms: u32, data: &[u8]) {
+        file.extend_from_slice(&u32_to_bytes(diff_ms));
+        file.extend_from_slice(&u32_to_bytes(data.len() as u32));
+        file.extend_from_slice(data);
+    }
+
+    fn read_all(file: Vec<u8>) -> (RecordReader<Cursor<Vec<u8>>>, Vec<Record>) {
+        let mut records = RecordReader::new(Cursor::new(file)).unwrap();
+        let mut read = vec![];
+
+        while let Some(record) = records.next_record().unwrap() {
+            read.push(record);
+        }
+
+        (records, read)
+    }
+
+    fn payloads(records: &[Record]) -> Vec<&[u8]> {
+        records.iter().map(|record| record.data.as_slice()).collect()
+    }
+
+    #[test]
+    fn reads_legacy_recordings() {
+        let mut file = vec![];
+        v1_record(&mut file, 0, b"first");
+        v1_record(&mut file, 250, b"second");
+
+        let (records, read) = read_all(file);
+        assert!(records.header.is_legacy());
+        assert_eq!(payloads(&read), [b"first".as_slice(), b"second"]);
+        assert_eq!(
+            read.iter().map(|record| (record.timestamp, record.delta, record.elapsed)).collect::<Vec<_>>(),
+            [(0, 0, 0), (0, 250 * MS, 250 * MS)]
+        );
+
+        // An empty file is an empty legacy recording, text is not a recording at all
+        assert!(RecordReader::new(Cursor::new(vec![])).unwrap().header.is_legacy());
+        assert!(RecordReader::new(Cursor::new(b"definitely not a recording".to_vec())).is_err());
+    }
+
+    #[test]
+    fn reads_version_1_times_from_creation_time() {
+        let (header, mut file) = recording(1, false, Compression::None);
+        v1_record(&mut file, 10, b"a");
+        v1_record(&mut file, 20, b"b");
+
+        let (records, read) = read_all(file);
+        let created_at = header.created_at_nanos().unwrap();
+
+        assert_eq!(records.header.version, 1);
+        assert_eq!(records.header.record_header_size(), 8);
+        assert_eq!(
+            read.iter().map(|record| (record.timestamp, record.monotonic)).collect::<Vec<_>>(),
+            [(created_at + 10 * MS, 10 * MS), (created_at + 30 * MS, 30 * MS)]
+        );
+    }
+
+    #[test]
+    fn round_trips_records_of_versions_2_to_4() {
+        for (version, checksums, header_size) in [(2, false, 22), (3, false, 22), (3, true, 26), (4, true, 26)] {
+            let (header, mut file) = recording(version, checksums, Compression::None);
+            let start = header.created_at_nanos().unwrap();
+
+            let mut writer = RecordWriter::new(&mut file, &header);
+            writer.write(start, 0, 0, b"one").unwrap();
+            writer.write(start + 3 * MS, 2 * MS, 0, &[0xff; 300]).unwrap();
+
+            let (records, read) = read_all(file);
+            assert_eq!(records.header.version, version);
+            assert_eq!(records.header.record_header_size(), header_size);
+            assert_eq!(payloads(&read), [b"one".as_slice(), &[0xff; 300]]);
+
+            // Deltas follow the monotonic clock
+            assert_eq!(
+                read.iter().map(|record| (record.timestamp, record.delta, record.elapsed)).collect::<Vec<_>>(),
+                [(start, 0, 0), (start + 3 * MS, 2 * MS, 2 * MS)]
+            );
+            assert_eq!(records.corruption, Corruption::default());
+        }
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_truncated_headers() {
+        let (_, mut file) = recording(FORMAT_VERSION + 1, false, Compression::None);
+        assert_eq!(RecordReader::new(Cursor::new(file.clone())).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        file[8..10].copy_from_slice(&FORMAT_VERSION.to_be_bytes());
+        file.truncate(20);
+        assert!(RecordReader::new(Cursor::new(file)).is_err());
+    }
+
+    #[test]
+    fn resyncs_past_corrupt_records() {
+        let (header, mut file) = recording(FORMAT_VERSION, true, Compression::None);
+        let start = header.created_at_nanos().unwrap();
+
+        let mut writer = RecordWriter::new(&mut file, &header);
+        writer.write(start, 0, 0, b"good").unwrap();
+        let corrupt = writer.get_ref().len();
+        writer.write(start + MS, MS, 0, b"flipped").unwrap();
+        let next = writer.get_ref().len();
+        writer.write(start + 2 * MS, 2 * MS, 0, b"after").unwrap();
+
+        // A flipped payload bit only shows in the CRC
+        file[next - 1] ^= 1;
+
+        let (records, read) = read_all(file);
+        assert_eq!(payloads(&read), [b"good".as_slice(), b"after"]);
+        assert_eq!(
+            records.corruption,
+            Corruption {
+                bad_records: 1,
+                skipped_bytes: (next - corrupt) as u64,
+            }
+        );
+    }
+
+    #[test]
+    fn resyncs_past_garbage_without_checksums() {
+        let (header, mut file) = recording(2, false, Compression::None);
+        let start = header.created_at_nanos().unwrap();
+
+        RecordWriter::new(&mut file, &header).write(start, 0, 0, b"before").unwrap();
+        file.extend_from_slice(&[0xee; 7]);
+        RecordWriter::new(&mut file, &header).write(start + MS, MS, 0, b"after").unwrap();
+        RecordWriter::new(&mut file, &header).write(start + 2 * MS, 2 * MS, 0, b"last").unwrap();
+
+        let (records, read) = read_all(file);
+        assert_eq!(payloads(&read), [b"before".as_slice(), b"after", b"last"]);
+        assert_eq!(records.corruption.skipped_bytes, 7);
+    }
+
+    #[test]
+    fn leaves_partial_record_until_completed() {
+        let (header, mut file) = recording(FORMAT_VERSION, true, Compression::None);
+        let start = header.created_at_nanos().unwrap();
+
+        let mut record = vec![];
+        RecordWriter::new(&mut record, &header).write(start, 0, 0, b"written in two parts").unwrap();
+        file.extend_from_slice(&record[..30]);
+
+        let mut records = RecordReader::new(Cursor::new(file)).unwrap();
+        assert!(records.next_record().unwrap().is_none());
+        assert_eq!(records.corruption, Corruption::default());
+
+        records.reader.get_mut().extend_from_slice(&record[30..]);
+        assert_eq!(records.next_record().unwrap().unwrap().data, b"written in two parts");
+    }
+
+    #[test]
+    fn round_trips_compressed_blocks() {
+        for compression in [Compression::Zstd, Compression::Lz4] {
+            let (mut header, _) = recording(FORMAT_VERSION, true, compression);
+            let start = header.created_at_nanos().unwrap();
+
+            let mut writer = RecordingWriter::create(vec![], &mut header).unwrap();
+            for i in 0..100u64 {
+                let record = Record {
+                    timestamp: start + i * MS,
+                    monotonic: i * MS,
+                    data: vec![i as u8; 50],
+                    ..Default::default()
+                };
+                writer.write_record(&record).unwrap();
+            }
+            let file = writer.finish().unwrap();
+
+            assert_eq!(file[header.data_offset as usize..][..4], BLOCK_MAGIC);
+            assert!(file.len() < 100 * 50, "{:?} did not compress", compression);
+
+            let (records, read) = read_all(file);
+            assert_eq!(records.header.compression, compression);
+            assert_eq!(read.len(), 100);
+            assert_eq!(read[99].data, [99; 50]);
+            assert_eq!(read[99].elapsed, 99 * MS);
+        }
+    }
+
+    #[test]
+    fn skips_compressed_blocks_with_bad_crc() {
+        let (header, mut file) = recording(FORMAT_VERSION, false, Compression::Lz4);
+        let start = header.created_at_nanos().unwrap();
+
+        let mut blocks = vec![];
+        for (i, data) in [b"first", b"corru", b"third"].iter().enumerate() {
+            let mut records = RecordWriter::new(vec![], &header);
+            records.write(start + i as u64 * MS, i as u64 * MS, 0, *data).unwrap();
+            blocks.push(encode_block(Compression::Lz4, 0, records.get_ref()).unwrap());
+        }
+
+        let last = blocks[1].len() - 1;
+        blocks[1][last] ^= 0xff;
+        let skipped = blocks[1].len() as u64;
+        file.extend(blocks.concat());
+
+        let (records, read) = read_all(file);
+        assert_eq!(payloads(&read), [b"first".as_slice(), b"third"]);
+        assert_eq!(
+            records.corruption,
+            Corruption {
+                bad_records: 1,
+                skipped_bytes: skipped,
+            }
+        );
+    }
+
+    /// Recording of two records followed by a third cut by `cut`, with the offset the third starts at
+    fn write_torn(name: &str, cut: impl Fn(&mut Vec<u8>, usize)) -> (String, u64) {
+        let path = std::env::temp_dir()
+            .join(format!("recorder-repair-{}-{}.rec", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let (header, mut file) = recording(FORMAT_VERSION, true, Compression::None);
+        let start = header.created_at_nanos().unwrap();
+
+        let mut writer = RecordWriter::new(&mut file, &header);
+        writer.write(start, 0, 0, b"first").unwrap();
+        writer.write(start + MS, MS, 0, b"second").unwrap();
+        let end = writer.get_ref().len();
+        writer.write(start + 2 * MS, 2 * MS, 0, b"cut off by a crash").unwrap();
+
+        cut(&mut file, end);
+        fs::write(&path, file).unwrap();
+
+        (path, end as u64)
+    }
+
+    /// Append a record the way the file adapter does after repairing
+    fn append(path: &str, data: &[u8]) {
+        let header = FileHeader::read_path(path).unwrap();
+        let start = header.created_at_nanos().unwrap();
+        let file = OpenOptions::new().append(true).open(path).unwrap();
+
+        RecordWriter::new(file, &header).write(start + 3 * MS, 3 * MS, 0, data).unwrap();
+    }
+
+    #[test]
+    fn repairs_torn_headers_payloads_and_bad_crc_tails() {
+        type Cut = fn(&mut Vec<u8>, usize);
+        let cuts: [(&str, Cut); 3] = [
+            ("header", |file, end| file.truncate(end + 10)),
+            ("payload", |file, end| file.truncate(end + 26 + 4)),
+            ("crc", |file, _| *file.last_mut().unwrap() ^= 1),
+        ];
+
+        for (name, cut) in cuts {
+            let (path, end) = write_torn(name, cut);
+            let torn = fs::read(&path).unwrap();
+
+            let tail = repair_tail(&path, TailRepair::Quarantine).unwrap().unwrap();
+            assert_eq!((tail.offset, tail.length), (end, torn.len() as u64 - end), "{}", name);
+            assert_eq!(fs::metadata(&path).unwrap().len(), end, "{}", name);
+
+            // The cut bytes are kept for inspection, records appended next read cleanly
+            let quarantined = quarantine_path(&path, end);
+            assert_eq!(fs::read(&quarantined).unwrap(), &torn[end as usize..], "{}", name);
+
+            append(&path, b"appended");
+            let (records, read) = read_all(fs::read(&path).unwrap());
+            assert_eq!(payloads(&read), [b"first".as_slice(), b"second", b"appended"], "{}", name);
+            assert_eq!(records.corruption, Corruption::default(), "{}", name);
+
+            assert!(repair_tail(&path, TailRepair::Truncate).unwrap().is_none());
+            fs::remove_file(&path).unwrap();
+            fs::remove_file(&quarantined).unwrap();
+        }
+    }
+
+    #[test]
+    fn truncates_without_quarantining_and_trims_the_index() {
+        let (path, end) = write_torn("truncate", |file, end| file.truncate(end + 10));
+        let entry = |record, offset| IndexEntry {
+            record,
+            offset,
+            ..Default::default()
+        };
+        let first = RecordReader::new(fs::File::open(&path).unwrap()).unwrap().offset();
+        index::Index {
+            entries: vec![entry(0, first), entry(2, end)],
+        }
+        .save(&path)
+        .unwrap();
+
+        repair_tail(&path, TailRepair::Truncate).unwrap().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), end);
+        assert!(!Path::new(&quarantine_path(&path, end)).exists());
+        assert_eq!(index::Index::load(&path).unwrap().entries.len(), 1);
+
+        fs::remove_file(index::index_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
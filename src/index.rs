@@ -0,0 +1,472 @@
+//! Sidecar index of a recording, stored next to it as `<recording>.idx`.
+//!
+//! `[magic: "RECINDEX"][version: u16]` followed by entries
+//! `[record: u64][offset: u64][timestamp ns: u64][monotonic ns: u64][elapsed ns: u64]`,
+//! all integers big endian. An entry is written for the first record and then every
+//! N records or M milliseconds of capture time, whichever comes first.
+//! Compressed recordings only get entries for records starting a block.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Error, ErrorKind, Read, Seek, Write},
+};
+
+use crate::{
+    format::{record_delta, Compression, RecordReader},
+    replay::WindowPosition,
+};
+
+pub const INDEX_MAGIC: [u8; 8] = *b"RECINDEX";
+pub const INDEX_VERSION: u16 = 1;
+
+const INDEX_HEADER_SIZE: u64 = 10;
+const ENTRY_SIZE: usize = 40;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IndexEntry {
+    /// Number of the record, counted from 0
+    pub record: u64,
+    /// Offset of the record in the recording
+    pub offset: u64,
+    pub timestamp: u64,
+    pub monotonic: u64,
+    /// Nanoseconds since the first record
+    pub elapsed: u64,
+}
+
+impl IndexEntry {
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+
+        for (i, value) in [self.record, self.offset, self.timestamp, self.monotonic, self.elapsed]
+            .iter()
+            .enumerate()
+        {
+            bytes[i * 8..(i + 1) * 8].copy_from_slice(&value.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; ENTRY_SIZE]) -> IndexEntry {
+        let field = |i: usize| u64::from_be_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
+
+        IndexEntry {
+            record: field(0),
+            offset: field(1),
+            timestamp: field(2),
+            monotonic: field(3),
+            elapsed: field(4),
+        }
+    }
+
+    /// Whether this entry lies at or after the window position
+    pub fn reached(&self, position: &WindowPosition) -> bool {
+        match position {
+            WindowPosition::Timestamp(timestamp) => self.timestamp >= *timestamp,
+            WindowPosition::Elapsed(elapsed) => self.elapsed >= *elapsed,
+        }
+    }
+}
+
+/// Path of the index belonging to a recording
+pub fn index_path(recording: &str) -> String {
+    format!("{}.idx", recording)
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Load the index of a recording, a partially written last entry is ignored.
+    /// Fails if the index does not exist or points past the end of the recording.
+    pub fn load(recording: &str) -> Result<Index, Error> {
+        let index = Index::read(recording)?;
+
+        let length = fs::metadata(recording)?.len();
+        if index.entries.iter().any(|entry| entry.offset >= length) {
+            return Err(Error::new(ErrorKind::InvalidData, "index points past the end of the recording"));
+        }
+
+        Ok(index)
+    }
+
+    fn read(recording: &str) -> Result<Index, Error> {
+        let mut bytes = vec![];
+        File::open(index_path(recording))?.read_to_end(&mut bytes)?;
+
+        if bytes.len() < INDEX_HEADER_SIZE as usize || bytes[..8] != INDEX_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a recording index"));
+        }
+
+        let version = u16::from_be_bytes([bytes[8], bytes[9]]);
+        if version != INDEX_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported index version {}", version),
+            ));
+        }
+
+        let entries: Vec<IndexEntry> = bytes[INDEX_HEADER_SIZE as usize..]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|chunk| IndexEntry::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(Index { entries })
+    }
+
+    /// Build the index of a recording by reading all of it
+    pub fn build<R: Read + Seek>(
+        records: &mut RecordReader<R>,
+        every_records: u64,
+        every_ms: u64,
+    ) -> Result<Index, Error> {
+        let mut index = Index::default();
+        let mut schedule = Schedule::new(every_records, every_ms);
+
+        records.rewind()?;
+
+        loop {
+            let record_number = records.position();
+
+            let Some(record) = records.next_record()? else {
+                break;
+            };
+
+            // Only known after reading, corrupt data in front of the record is skipped
+            let Some(offset) = records.record_offset() else {
+                continue;
+            };
+
+            let entry = IndexEntry {
+                record: record_number,
+                offset,
+                timestamp: record.timestamp,
+                monotonic: record.monotonic,
+                elapsed: record.elapsed,
+            };
+
+            if schedule.due(&entry) {
+                index.entries.push(entry);
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Write the index next to the recording, replacing any existing one
+    pub fn save(&self, recording: &str) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(INDEX_HEADER_SIZE as usize + self.entries.len() * ENTRY_SIZE);
+        bytes.extend_from_slice(&INDEX_MAGIC);
+        bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.to_bytes());
+        }
+
+        fs::write(index_path(recording), bytes)
+    }
+
+    /// Last entry at or before the record number and before the window position, None if neither is given.
+    /// Only the given ones limit the search.
+    pub fn find(&self, record: Option<u64>, position: Option<&WindowPosition>) -> Option<&IndexEntry> {
+        if record.is_none() && position.is_none() {
+            return None;
+        }
+
+        self.entries
+            .iter()
+            .take_while(|entry| {
+                record.is_none_or(|record| entry.record <= record) && !position.is_some_and(|p| entry.reached(p))
+            })
+            .last()
+    }
+}
+
+/// Drop index entries at or past `length`, after the recording was cut to that length.
+/// A recording without an index is left alone.
+pub fn trim(recording: &str, length: u64) -> Result<(), Error> {
+    let mut index = match Index::read(recording) {
+        Ok(index) => index,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    index.entries.retain(|entry| entry.offset < length);
+    index.save(recording)
+}
+
+/// Decides when the next index entry is due
+#[derive(Debug)]
+struct Schedule {
+    every_records: u64,
+    every_ns: u64,
+    last: Option<IndexEntry>,
+}
+
+impl Schedule {
+    fn new(every_records: u64, every_ms: u64) -> Schedule {
+        Schedule {
+            every_records,
+            every_ns: every_ms * 1_000_000,
+            last: None,
+        }
+    }
+
+    fn due(&mut self, entry: &IndexEntry) -> bool {
+        let due = match self.last {
+            None => true,
+            Some(last) => {
+                (self.every_records > 0 && entry.record - last.record >= self.every_records)
+                    || (self.every_ns > 0 && entry.elapsed.saturating_sub(last.elapsed) >= self.every_ns)
+            }
+        };
+
+        if due {
+            self.last = Some(*entry);
+        }
+
+        due
+    }
+}
+
+/// Maintains the index of a recording while it is being written
+#[derive(Debug)]
+pub struct IndexWriter {
+    file: File,
+    schedule: Schedule,
+    /// Entry the next record will get
+    next: IndexEntry,
+    prev_timestamp: u64,
+    prev_monotonic: u64,
+    /// Size of the headers in front of every payload in the recording
+    record_header_size: u64,
+    /// Records are written in compressed blocks
+    compressed: bool,
+    /// The next record starts a compressed block
+    block_started: bool,
+}
+
+impl IndexWriter {
+    /// Open the index of a recording for appending.
+    /// If the recording already has records, the existing index is reused or rebuilt
+    /// and the records after its last entry are read to continue counting.
+    pub fn open(recording: &str, every_records: u64, every_ms: u64) -> Result<IndexWriter, Error> {
+        let mut records = RecordReader::new(BufReader::new(File::open(recording)?))?;
+
+        // A stale index fails to load or to resume from, rebuild it then
+        if let Ok(index) = Index::load(recording) {
+            if let Ok(writer) = IndexWriter::resume(recording, &mut records, &index, every_records, every_ms) {
+                return Ok(writer);
+            }
+
+            println!("Index of {} is stale, rebuilding it", recording);
+        }
+
+        let index = Index::build(&mut records, every_records, every_ms)?;
+        index.save(recording)?;
+
+        IndexWriter::resume(recording, &mut records, &index, every_records, every_ms)
+    }
+
+    fn resume<R: Read + Seek>(
+        recording: &str,
+        records: &mut RecordReader<R>,
+        index: &Index,
+        every_records: u64,
+        every_ms: u64,
+    ) -> Result<IndexWriter, Error> {
+        let mut writer = IndexWriter {
+            file: OpenOptions::new().append(true).open(index_path(recording))?,
+            schedule: Schedule::new(every_records, every_ms),
+            next: IndexEntry {
+                record: 0,
+                offset: records.header.data_offset,
+                ..Default::default()
+            },
+            prev_timestamp: 0,
+            prev_monotonic: 0,
+            record_header_size: records.header.record_header_size(),
+            compressed: records.header.compression != Compression::None,
+            block_started: false,
+        };
+
+        if let Some(last) = index.entries.last() {
+            writer.schedule.last = Some(*last);
+            records.seek_to(last)?;
+
+            while let Some(record) = records.next_record()? {
+                writer.prev_timestamp = record.timestamp;
+                writer.prev_monotonic = record.monotonic;
+                writer.next.elapsed = record.elapsed;
+            }
+
+            writer.next.record = records.position();
+            writer.next.offset = records.offset();
+        }
+
+        Ok(writer)
+    }
+
+    /// The next record starts a compressed block at an offset
+    pub fn start_block(&mut self, offset: u64) {
+        self.next.offset = offset;
+        self.block_started = true;
+    }
+
+    /// Account for a record about to be written at the end of the recording,
+    /// writes an index entry for it if one is due
+    pub fn add(&mut self, timestamp: u64, monotonic: u64, size: usize) -> Result<(), Error> {
+        let mut entry = self.next;
+        entry.timestamp = timestamp;
+        entry.monotonic = monotonic;
+
+        if entry.record > 0 {
+            entry.elapsed += record_delta(self.prev_timestamp, self.prev_monotonic, timestamp, monotonic);
+        }
+
+        let seekable = !self.compressed || std::mem::take(&mut self.block_started);
+
+        if seekable && self.schedule.due(&entry) {
+            self.file.write_all(&entry.to_bytes())?;
+        }
+
+        self.prev_timestamp = timestamp;
+        self.prev_monotonic = monotonic;
+        self.next = IndexEntry {
+            record: entry.record + 1,
+            offset: entry.offset + self.record_header_size + size as u64,
+            elapsed: entry.elapsed,
+            ..Default::default()
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Cursor, process};
+
+    use super::*;
+    use crate::format::{encode_block, FileHeader, RecordWriter};
+
+    const SECOND: u64 = 1_000_000_000;
+    const START: u64 = 1_700_000_000 * SECOND;
+
+    fn entry(record: u64, timestamp: u64) -> IndexEntry {
+        IndexEntry {
+            record,
+            offset: 100 + record * 30,
+            timestamp,
+            monotonic: timestamp - START,
+            elapsed: timestamp - START,
+        }
+    }
+
+    /// Compressed recording with `blocks` blocks of `per_block` records, one a second
+    fn compressed_recording(blocks: u64, per_block: u64) -> Vec<u8> {
+        let mut header = FileHeader::new(vec![]);
+        header.checksums = true;
+        header.compression = Compression::Zstd;
+
+        let mut file = vec![];
+        header.write_to(&mut file).unwrap();
+
+        for block in 0..blocks {
+            let mut records = RecordWriter::new(vec![], &header);
+            for record in block * per_block..(block + 1) * per_block {
+                records.write(START + record * SECOND, record * SECOND, 0, &record.to_be_bytes()).unwrap();
+            }
+
+            file.extend(encode_block(Compression::Zstd, 0, records.get_ref()).unwrap());
+        }
+
+        file
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let entry = entry(7, START + 3 * SECOND);
+        let decoded = IndexEntry::from_bytes(&entry.to_bytes());
+
+        assert_eq!(
+            (decoded.record, decoded.offset, decoded.timestamp, decoded.monotonic, decoded.elapsed),
+            (entry.record, entry.offset, entry.timestamp, entry.monotonic, entry.elapsed)
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_ignoring_partial_last_entry() {
+        let recording = env::temp_dir().join(format!("recorder-index-test-{}", process::id()));
+        let recording = recording.to_str().unwrap();
+        fs::write(recording, vec![0; 1000]).unwrap();
+
+        let index = Index {
+            entries: vec![entry(0, START), entry(10, START + SECOND)],
+        };
+        index.save(recording).unwrap();
+
+        // A writer killed mid-entry leaves part of one behind
+        OpenOptions::new().append(true).open(index_path(recording)).unwrap().write_all(&[1; 12]).unwrap();
+        let loaded = Index::load(recording).unwrap();
+        assert_eq!(loaded.entries.iter().map(|entry| entry.record).collect::<Vec<_>>(), [0, 10]);
+
+        // The recording was cut before the last entry
+        fs::write(recording, vec![0; 300]).unwrap();
+        assert_eq!(Index::load(recording).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        fs::write(index_path(recording), b"NOTINDEX\0\x01").unwrap();
+        assert_eq!(Index::load(recording).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(recording).unwrap();
+        fs::remove_file(index_path(recording)).unwrap();
+    }
+
+    #[test]
+    fn find_is_only_limited_by_given_bounds() {
+        let index = Index {
+            entries: (0..4).map(|i| entry(i * 10, START + i * 10 * SECOND)).collect(),
+        };
+        let record = |entry: Option<&IndexEntry>| entry.map(|entry| entry.record);
+
+        assert_eq!(record(index.find(None, None)), None);
+        assert_eq!(record(index.find(Some(25), None)), Some(20));
+        assert_eq!(record(index.find(Some(1000), None)), Some(30));
+        assert_eq!(record(index.find(None, Some(&WindowPosition::Timestamp(START + 25 * SECOND)))), Some(20));
+        assert_eq!(record(index.find(None, Some(&WindowPosition::Elapsed(20 * SECOND)))), Some(10));
+        assert_eq!(record(index.find(Some(15), Some(&WindowPosition::Elapsed(25 * SECOND)))), Some(10));
+        assert_eq!(record(index.find(None, Some(&WindowPosition::Timestamp(START)))), None);
+    }
+
+    #[test]
+    fn seeks_multi_block_recording_by_time() {
+        let mut records = RecordReader::new(Cursor::new(compressed_recording(4, 5))).unwrap();
+        let index = Index::build(&mut records, 1, 0).unwrap();
+
+        // Only block starts can be seeked to
+        assert_eq!(index.entries.iter().map(|entry| entry.record).collect::<Vec<_>>(), [0, 5, 10, 15]);
+
+        let start = WindowPosition::Timestamp(START + 12 * SECOND);
+        let entry = index.find(None, Some(&start)).unwrap();
+        assert_eq!(entry.record, 10);
+
+        records.seek_to(entry).unwrap();
+        let record = records.next_record().unwrap().unwrap();
+        assert_eq!(
+            (record.timestamp, record.elapsed, record.data),
+            (START + 10 * SECOND, 10 * SECOND, 10u64.to_be_bytes().to_vec())
+        );
+
+        while let Some(record) = records.next_record().unwrap() {
+            if start.reached(&record) {
+                assert_eq!(record.data, 12u64.to_be_bytes());
+                return;
+            }
+        }
+
+        panic!("window start not found after seeking");
+    }
+}
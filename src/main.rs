@@ -1,22 +1,53 @@
-use std::{env, sync::Arc};
+use std::{env, process, sync::Arc};
 
-use adapters::{file_adapter::FileAdapter, tcp_client_adapter::TcpClientAdapter, tcp_proxy::TcpProxyAdapter, udp_adapter::UdpAdapter};
+use adapters::{
+    file_adapter::FileAdapter, fixture_adapter::FixtureAdapter, merge_adapter::MergeAdapter, pcap_adapter::PcapAdapter,
+    tcp_client_adapter::TcpClientAdapter, tcp_proxy::TcpProxyAdapter, tcp_server_adapter::TcpServerAdapter,
+    udp_adapter::UdpAdapter,
+};
 use recorder::{AdapterType, Mode, Recorder};
 
 mod adapters;
 mod constants;
+mod fixture;
+mod format;
+mod index;
+mod merge;
+mod message;
+mod pcap;
 mod recorder;
+mod replay;
+mod retention;
+mod rollover;
+mod status;
+mod template;
+mod tools;
 mod utils;
 
 fn main() {
-    let config_path: String = env::args().nth(1).unwrap_or("settings.json".to_string());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // Offline tools are subcommands, anything else is the settings path
+    if let Some(result) = tools::run(&args) {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    let config_path: String = args.first().cloned().unwrap_or("settings.json".to_string());
 
     // Create all adapter instances
     let tcp_client_adapter = Arc::new(TcpClientAdapter {});
-    let tcp_server_adapter = Arc::new(TcpClientAdapter {});
+    let tcp_server_adapter = Arc::new(TcpServerAdapter {});
     let tcp_proxy_adapter = Arc::new(TcpProxyAdapter {});
     let file_adapter = Arc::new(FileAdapter {});
     let udp_adapter = Arc::new(UdpAdapter {});
+    let pcap_adapter = Arc::new(PcapAdapter {});
+    let merge_adapter = Arc::new(MergeAdapter {});
+    let fixture_adapter = Arc::new(FixtureAdapter {});
 
     // Register all adapters here
     let mapping: Vec<(Mode, AdapterType)> = vec![
@@ -26,17 +57,33 @@ fn main() {
         (Mode::TcpProxy, AdapterType::Input(tcp_proxy_adapter.clone())),
         (Mode::File, AdapterType::Input(file_adapter.clone())),
         (Mode::Udp, AdapterType::Input(udp_adapter.clone())),
+        (Mode::Pcap, AdapterType::Input(pcap_adapter.clone())),
+        (Mode::Merge, AdapterType::Input(merge_adapter.clone())),
+        (Mode::Fixture, AdapterType::Input(fixture_adapter.clone())),
         // Output adapters
         (Mode::TcpClient, AdapterType::Output(tcp_client_adapter.clone())),
         (Mode::TcpServer, AdapterType::Output(tcp_server_adapter.clone())),
         (Mode::File, AdapterType::Output(file_adapter.clone())),
         (Mode::Udp, AdapterType::Output(udp_adapter.clone())),
+        (Mode::Pcap, AdapterType::Output(pcap_adapter.clone())),
     ];
 
     let recorder = Recorder::new(config_path, mapping);
 
     // First start writer thread
-    recorder.write();
-    // Start reader thread and then block on it
-    recorder.read().join().unwrap();
+    let outputs = recorder.write();
+    // Start reader threads and then block on all of them, they report their own status as they stop
+    for (block, handle) in recorder.read() {
+        if handle.join().is_err() {
+            println!("Input {:?} panicked", block.mode);
+        }
+    }
+
+    // Outputs write everything still buffered before the recorder exits
+    recorder.close();
+    for (block, handle) in outputs {
+        if handle.join().is_err() {
+            println!("Output {:?} panicked", block.mode);
+        }
+    }
 }
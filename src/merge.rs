@@ -0,0 +1,241 @@
+//! Merging of several recordings into one stream in capture time order.
+//!
+//! Records are ordered by wall clock timestamp, ties go to the recording listed first.
+//! Every recording stays in its own order, so a clock step inside one of them cannot reorder it.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read, Seek},
+};
+
+use serde_json::json;
+
+use crate::{
+    format::{Record, RecordReader},
+    recorder::Block,
+};
+
+pub struct Merger<R> {
+    pub readers: Vec<RecordReader<R>>,
+    /// Next record of every recording which has one left
+    pending: Vec<Option<Record>>,
+    /// Timestamp and recording of every pending record, earliest first
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl Merger<BufReader<File>> {
+    /// Open recordings for merging, legacy recordings are rejected as they have no capture times
+    pub fn open(paths: &[String]) -> Result<Merger<BufReader<File>>, Error> {
+        let readers = paths
+            .iter()
+            .map(|path| {
+                let records = RecordReader::new(BufReader::with_capacity(131072, File::open(path)?))?;
+
+                if records.header.is_legacy() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} is a legacy recording without capture times, convert it first", path),
+                    ));
+                }
+
+                Ok(records)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Merger::new(readers)
+    }
+}
+
+impl<R: Read + Seek> Merger<R> {
+    pub fn new(readers: Vec<RecordReader<R>>) -> Result<Merger<R>, Error> {
+        let mut merger = Merger {
+            pending: readers.iter().map(|_| None).collect(),
+            readers,
+            queue: BinaryHeap::new(),
+        };
+
+        for source in 0..merger.readers.len() {
+            merger.fill(source)?;
+        }
+
+        Ok(merger)
+    }
+
+    /// Next record of all recordings with the index of the recording it comes from
+    pub fn next_record(&mut self) -> Result<Option<(usize, Record)>, Error> {
+        let Some(Reverse((_, source))) = self.queue.pop() else {
+            return Ok(None);
+        };
+
+        let record = self.pending[source].take().unwrap();
+        self.fill(source)?;
+
+        Ok(Some((source, record)))
+    }
+
+    /// Timestamp of the record `next_record` returns
+    pub fn peek_timestamp(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse((timestamp, _))| *timestamp)
+    }
+
+    /// Read again from recordings which ended, to pick up records appended since
+    pub fn refill(&mut self) -> Result<(), Error> {
+        for source in 0..self.readers.len() {
+            if self.pending[source].is_none() {
+                self.fill(source)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start all recordings over
+    pub fn rewind(&mut self) -> Result<(), Error> {
+        self.queue.clear();
+
+        for source in 0..self.readers.len() {
+            self.readers[source].rewind()?;
+            self.fill(source)?;
+        }
+
+        Ok(())
+    }
+
+    /// Inputs of all recordings one after another, see `input_offsets`.
+    /// Recordings without inputs get one describing the recording, so their records keep a tag.
+    pub fn inputs(&self, paths: &[String]) -> Result<Vec<Block>, Error> {
+        let mut inputs = vec![];
+
+        for (records, path) in self.readers.iter().zip(paths) {
+            if records.header.inputs.is_empty() {
+                inputs.push(serde_json::from_value(json!({ "mode": "file", "file_path": path }))?);
+            } else {
+                inputs.extend(records.header.inputs.iter().cloned());
+            }
+        }
+
+        Ok(inputs)
+    }
+
+    /// Where the inputs of every recording start in `inputs`
+    pub fn input_offsets(&self) -> Vec<usize> {
+        self.readers
+            .iter()
+            .scan(0, |offset, records| {
+                let start = *offset;
+                *offset += records.header.inputs.len().max(1);
+                Some(start)
+            })
+            .collect()
+    }
+
+    fn fill(&mut self, source: usize) -> Result<(), Error> {
+        self.pending[source] = self.readers[source].next_record()?;
+
+        if let Some(record) = &self.pending[source] {
+            self.queue.push(Reverse((record.timestamp, source)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::format::{FileHeader, RecordWriter};
+
+    const MS: u64 = 1_000_000;
+
+    /// Recording with records captured at `times` ms after its creation, tagged with `tag` and their number
+    fn recording(tag: u8, times: &[u64]) -> RecordReader<Cursor<Vec<u8>>> {
+        let mut header = FileHeader::new(vec![]);
+        header.checksums = true;
+        let start = header.created_at_nanos().unwrap();
+
+        let mut file = vec![];
+        header.write_to(&mut file).unwrap();
+        let mut writer = RecordWriter::new(&mut file, &header);
+        for (i, time) in times.iter().enumerate() {
+            writer.write(start + time * MS, time * MS, 0, &[tag, i as u8]).unwrap();
+        }
+
+        RecordReader::new(Cursor::new(file)).unwrap()
+    }
+
+    /// Source and payload of every merged record
+    fn merge_all(recordings: Vec<RecordReader<Cursor<Vec<u8>>>>) -> Vec<(usize, Vec<u8>)> {
+        let mut merger = Merger::new(recordings).unwrap();
+        let mut merged = vec![];
+
+        while let Some((source, record)) = merger.next_record().unwrap() {
+            merged.push((source, record.data));
+        }
+
+        assert_eq!(merger.peek_timestamp(), None);
+        merged
+    }
+
+    #[test]
+    fn orders_records_by_capture_time_across_recordings() {
+        let merged = merge_all(vec![recording(b'a', &[10, 30, 50]), recording(b'b', &[20, 40])]);
+        assert_eq!(
+            merged,
+            [
+                (0, vec![b'a', 0]),
+                (1, vec![b'b', 0]),
+                (0, vec![b'a', 1]),
+                (1, vec![b'b', 1]),
+                (0, vec![b'a', 2]),
+            ]
+        );
+
+        // A clock step back inside a recording does not reorder it
+        let merged = merge_all(vec![recording(b'a', &[10, 18]), recording(b'b', &[12, 30, 16])]);
+        assert_eq!(
+            merged,
+            [
+                (0, vec![b'a', 0]),
+                (1, vec![b'b', 0]),
+                (0, vec![b'a', 1]),
+                (1, vec![b'b', 1]),
+                (1, vec![b'b', 2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_recording_listed_first() {
+        let merged = merge_all(vec![
+            recording(b'a', &[10, 20]),
+            recording(b'b', &[5, 10, 20]),
+            recording(b'c', &[10]),
+        ]);
+        let sources: Vec<_> = merged.iter().map(|(source, _)| *source).collect();
+
+        assert_eq!(sources, [1, 0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn continues_with_the_rest_once_a_recording_ends() {
+        let mut merger = Merger::new(vec![recording(b'a', &[10]), recording(b'b', &[20, 30]), recording(b'c', &[])])
+            .unwrap();
+
+        assert_eq!(merger.next_record().unwrap().unwrap().0, 0);
+        assert!(merger.peek_timestamp().is_some());
+        assert_eq!(merger.next_record().unwrap().unwrap().0, 1);
+        assert_eq!(merger.next_record().unwrap().unwrap().0, 1);
+        assert!(merger.next_record().unwrap().is_none());
+
+        // Refilling finds nothing new, rewinding starts every recording over
+        merger.refill().unwrap();
+        assert!(merger.next_record().unwrap().is_none());
+        merger.rewind().unwrap();
+        let sources: Vec<_> = std::iter::from_fn(|| merger.next_record().unwrap().map(|(source, _)| source)).collect();
+        assert_eq!(sources, [0, 1, 1]);
+    }
+}
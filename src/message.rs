@@ -0,0 +1,35 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+/// Envelope carried on the bus for every packet read by an input.
+/// Cloning is cheap, the payload is reference counted and shared by every output.
+#[derive(Clone, Debug)]
+pub struct Message {
+    /// Wall clock time at which the input captured the packet
+    pub wall_time: SystemTime,
+    /// Monotonic time at which the input captured the packet
+    pub mono_time: Instant,
+    /// Index of the input block which captured the packet
+    pub input_id: usize,
+    /// Address the packet was received from, if the input knows it
+    pub source: Option<SocketAddr>,
+    /// Group and port a replayed packet was originally sent to, from the input which recorded it
+    pub destination: Option<SocketAddr>,
+    /// Per input counter, starts at 0 for the first packet of every input
+    pub seq: u64,
+    /// Exactly sized payload
+    pub data: Arc<[u8]>,
+}
+
+impl Message {
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn size(&self) -> u32 {
+        self.data.len() as u32
+    }
+}
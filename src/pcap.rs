@@ -0,0 +1,765 @@
+//! Reading and writing pcap and pcapng captures of UDP over IPv4.
+//!
+//! Readers accept classic pcap in either byte order with micro or nanosecond timestamps,
+//! and pcapng with any number of sections and interfaces. Ethernet with VLAN tags, raw IPv4,
+//! BSD loopback and Linux cooked captures are understood. Everything but unfragmented UDP
+//! over IPv4 is skipped. Writers synthesise Ethernet, IPv4 and UDP headers around payloads.
+
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::Path,
+};
+
+use crate::recorder::Block;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+/// Largest pcapng block read, anything bigger is taken for corruption
+const MAX_BLOCK_SIZE: usize = 16 << 20;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+const IP_PROTOCOL_UDP: u8 = 17;
+/// Ethernet, IPv4 and UDP headers in front of every written payload
+const FRAME_HEADER_SIZE: usize = 14 + 20 + 8;
+
+/// A UDP datagram of a capture
+#[derive(Debug, Clone)]
+pub struct Packet {
+    /// Capture time in ns since the epoch
+    pub timestamp: u64,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub payload: Vec<u8>,
+}
+
+/// Container format of a capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Pcap,
+    Pcapng,
+}
+
+impl CaptureFormat {
+    /// pcapng for `.pcapng` files, classic pcap otherwise
+    pub fn from_path(path: &str) -> CaptureFormat {
+        match Path::new(path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("pcapng") => CaptureFormat::Pcapng,
+            _ => CaptureFormat::Pcap,
+        }
+    }
+}
+
+/// Addresses of a packet captured by an input, the group or host the input reads from is the
+/// destination, the sender if known or else the input's interface is the source
+pub fn input_addresses(input: Option<&Block>, sender: Option<SocketAddr>) -> (SocketAddrV4, SocketAddrV4) {
+    let ip = |ip: &str| ip.parse().unwrap_or(Ipv4Addr::UNSPECIFIED);
+
+    let destination = match input {
+        Some(input) => SocketAddrV4::new(ip(&input.source_ip), input.source_port),
+        None => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+    };
+
+    let source = match sender {
+        Some(SocketAddr::V4(sender)) => sender,
+        _ => SocketAddrV4::new(input.map_or(Ipv4Addr::UNSPECIFIED, |input| ip(&input.interface_ip)), destination.port()),
+    };
+
+    (source, destination)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    /// Timestamp ticks per second
+    resolution: u64,
+}
+
+#[derive(Debug)]
+enum Container {
+    Pcap { interface: Interface },
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+/// Reads the UDP packets of a pcap or pcapng capture
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+    pub format: CaptureFormat,
+    container: Container,
+    big_endian: bool,
+    /// Frames which are not UDP over IPv4
+    pub skipped: u64,
+    /// Bytes read from the start of the capture
+    offset: u64,
+    /// Offset after the last block or packet read whole, see `resume`
+    complete: u64,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Read the file header, telling pcap and pcapng apart by their magic
+    pub fn new(mut reader: R) -> Result<CaptureReader<R>, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut capture = CaptureReader {
+                reader,
+                format: CaptureFormat::Pcapng,
+                container: Container::Pcapng { interfaces: vec![] },
+                big_endian: false,
+                skipped: 0,
+                offset: magic.len() as u64,
+                complete: 0,
+            };
+            capture.read_section_header()?;
+            return Ok(capture);
+        }
+
+        let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_MICROS, _) => (false, false),
+            (PCAP_MAGIC_NANOS, _) => (false, true),
+            (_, PCAP_MAGIC_MICROS) => (true, false),
+            (_, PCAP_MAGIC_NANOS) => (true, true),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "not a pcap or pcapng capture")),
+        };
+
+        let mut header = [0; 20];
+        reader.read_exact(&mut header)?;
+
+        let mut capture = CaptureReader {
+            reader,
+            format: CaptureFormat::Pcap,
+            container: Container::Pcap {
+                interface: Interface {
+                    link_type: 0,
+                    resolution: if nanos { 1_000_000_000 } else { 1_000_000 },
+                },
+            },
+            big_endian,
+            skipped: 0,
+            offset: (magic.len() + header.len()) as u64,
+            complete: 0,
+        };
+
+        let link_type = capture.u32(&header[16..20]);
+        if let Container::Pcap { interface } = &mut capture.container {
+            interface.link_type = link_type & 0x0fff_ffff;
+        }
+
+        Ok(capture)
+    }
+
+    /// Next UDP packet, None at the end of the capture or at a packet cut off by it
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, Error> {
+        while let Some((interface, timestamp, frame)) = self.next_frame()? {
+            match udp_datagram(interface.link_type, &frame) {
+                Some((source, destination, payload)) => {
+                    return Ok(Some(Packet {
+                        timestamp: (timestamp as u128 * 1_000_000_000 / interface.resolution as u128) as u64,
+                        source,
+                        destination,
+                        payload: payload.to_vec(),
+                    }))
+                }
+                None => self.skipped += 1,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Next captured frame with its interface and timestamp in ticks of the interface
+    fn next_frame(&mut self) -> Result<Option<(Interface, u64, Vec<u8>)>, Error> {
+        self.complete = self.offset;
+
+        if let Container::Pcap { interface } = self.container {
+            let mut header = [0; 16];
+            if !self.read_or_end(&mut header)? {
+                return Ok(None);
+            }
+
+            let timestamp = self.u32(&header[0..4]) as u64 * interface.resolution + self.u32(&header[4..8]) as u64;
+            let length = self.u32(&header[8..12]) as usize;
+            if length > MAX_BLOCK_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, format!("bad pcap packet length {}", length)));
+            }
+
+            let mut frame = vec![0; length];
+
+            if !self.read_or_end(&mut frame)? {
+                return Ok(None);
+            }
+
+            self.complete = self.offset;
+            return Ok(Some((interface, timestamp, frame)));
+        }
+
+        loop {
+            self.complete = self.offset;
+
+            let mut header = [0; 8];
+            if !self.read_or_end(&mut header)? {
+                return Ok(None);
+            }
+
+            if u32::from_le_bytes(header[0..4].try_into().unwrap()) == PCAPNG_SECTION_HEADER {
+                // New section, possibly in the other byte order, with interfaces of its own
+                let length = header[4..8].try_into().unwrap();
+                if !self.read_section(length)? {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let block_type = self.u32(&header[0..4]);
+            let length = self.u32(&header[4..8]) as usize;
+            if length < 12 || !length.is_multiple_of(4) || length > MAX_BLOCK_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, format!("bad pcapng block length {}", length)));
+            }
+
+            // Body and trailing length
+            let mut body = vec![0; length - 8];
+            if !self.read_or_end(&mut body)? {
+                return Ok(None);
+            }
+            let body = &body[..body.len() - 4];
+
+            let (interface_id, timestamp, captured) = match block_type {
+                PCAPNG_INTERFACE if body.len() >= 8 => {
+                    let interface = Interface {
+                        link_type: self.u16(&body[0..2]) as u32,
+                        resolution: self.resolution(&body[8..]),
+                    };
+
+                    if let Container::Pcapng { interfaces } = &mut self.container {
+                        interfaces.push(interface);
+                    }
+                    continue;
+                }
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => (
+                    self.u32(&body[0..4]) as usize,
+                    (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64,
+                    (self.u32(&body[12..16]) as usize, 20),
+                ),
+                PCAPNG_PACKET if body.len() >= 20 => (
+                    self.u16(&body[0..2]) as usize,
+                    (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64,
+                    (self.u32(&body[12..16]) as usize, 20),
+                ),
+                // Simple packet blocks have no timestamp, statistics and name resolution don't matter
+                _ => {
+                    self.skipped += (block_type == 3) as u64;
+                    continue;
+                }
+            };
+
+            let (length, start) = captured;
+            let Container::Pcapng { interfaces } = &self.container else {
+                unreachable!()
+            };
+            let (Some(interface), Some(frame)) = (interfaces.get(interface_id), body.get(start..start + length)) else {
+                return Err(Error::new(ErrorKind::InvalidData, "pcapng packet of unknown interface or cut off"));
+            };
+
+            self.complete = self.offset;
+            return Ok(Some((*interface, timestamp, frame.to_vec())));
+        }
+    }
+
+    /// Rest of the first section header block, after its type
+    fn read_section_header(&mut self) -> Result<(), Error> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+        self.offset += length.len() as u64;
+
+        if !self.read_section(length)? {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "pcapng section header cut off"));
+        }
+
+        Ok(())
+    }
+
+    /// Rest of a section header block after its type and length, whose byte order is not known yet
+    fn read_section(&mut self, length: [u8; 4]) -> Result<bool, Error> {
+        let mut magic = [0; 4];
+        if !self.read_or_end(&mut magic)? {
+            return Ok(false);
+        }
+
+        self.big_endian = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+            (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "bad pcapng byte order magic")),
+        };
+
+        let length = self.u32(&length) as usize;
+        if length < 28 || !length.is_multiple_of(4) || length > MAX_BLOCK_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("bad pcapng section length {}", length)));
+        }
+
+        let mut rest = vec![0; length - 12];
+        if !self.read_or_end(&mut rest)? {
+            return Ok(false);
+        }
+
+        self.container = Container::Pcapng { interfaces: vec![] };
+        Ok(true)
+    }
+
+    /// Timestamp ticks per second from the options of an interface description block
+    fn resolution(&self, mut options: &[u8]) -> u64 {
+        while options.len() >= 4 {
+            let code = self.u16(&options[0..2]);
+            let length = self.u16(&options[2..4]) as usize;
+
+            // if_tsresol, a power of 10 or, with the top bit set, of 2
+            if code == 9 && length == 1 && options.len() > 4 {
+                let exponent = options[4] as u32;
+                return match exponent & 0x80 {
+                    0 => 10u64.checked_pow(exponent).unwrap_or(1_000_000),
+                    _ => 2u64.checked_pow(exponent & 0x7f).unwrap_or(1_000_000),
+                };
+            }
+
+            if code == 0 {
+                break;
+            }
+
+            options = options.get(4 + length.div_ceil(4) * 4..).unwrap_or_default();
+        }
+
+        1_000_000
+    }
+
+    /// Fill `buf`, false if the capture ends first
+    fn read_or_end(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+/// Addresses and payload of an unfragmented UDP over IPv4 frame
+fn udp_datagram(link_type: u32, frame: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    let be16 = |bytes: &[u8], at: usize| bytes.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+    let ip = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ethertype = be16(frame, at)?;
+            while ETHERTYPE_VLAN.contains(&ethertype) {
+                at += 4;
+                ethertype = be16(frame, at)?;
+            }
+            (ethertype == ETHERTYPE_IPV4).then_some(frame.get(at + 2..)?)?
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 => frame,
+        // Address family in host byte order of the capturing machine
+        LINKTYPE_NULL => {
+            let family = frame.get(..4)?;
+            (family == [2, 0, 0, 0] || family == [0, 0, 0, 2]).then_some(frame.get(4..)?)?
+        }
+        LINKTYPE_LINUX_SLL => (be16(frame, 14)? == ETHERTYPE_IPV4).then_some(frame.get(16..)?)?,
+        LINKTYPE_LINUX_SLL2 => (be16(frame, 0)? == ETHERTYPE_IPV4).then_some(frame.get(20..)?)?,
+        _ => return None,
+    };
+
+    let header_length = (*ip.first()? & 0x0f) as usize * 4;
+    let fragment = be16(ip, 6)?;
+    if ip[0] >> 4 != 4 || header_length < 20 || *ip.get(9)? != IP_PROTOCOL_UDP || fragment & 0x3fff != 0 {
+        return None;
+    }
+
+    // Trailing Ethernet padding is not part of the datagram
+    let total_length = (be16(ip, 2)? as usize).clamp(header_length, ip.len());
+    let udp = ip.get(header_length..total_length)?;
+    let udp_length = (be16(udp, 4)? as usize).clamp(8, udp.len());
+
+    let address = |at: usize| Ipv4Addr::new(ip[at], ip[at + 1], ip[at + 2], ip[at + 3]);
+    let source = SocketAddrV4::new(address(12), be16(udp, 0)?);
+    let destination = SocketAddrV4::new(address(16), be16(udp, 2)?);
+
+    Some((source, destination, &udp[8..udp_length]))
+}
+
+impl<R: Read + Seek> CaptureReader<R> {
+    /// Continue after the last packet read whole, once `next_packet` returned None.
+    /// Reads packets appended to a growing capture since, including one the end had cut off.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.reader.seek(SeekFrom::Start(self.complete))?;
+        self.offset = self.complete;
+        Ok(())
+    }
+}
+
+/// Writes packets to a pcap capture with nanosecond timestamps or a pcapng capture
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    writer: W,
+    format: CaptureFormat,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Write the file header, and for pcapng the interface all packets are on
+    pub fn new(mut writer: W, format: CaptureFormat) -> Result<CaptureWriter<W>, Error> {
+        let mut header = vec![];
+
+        match format {
+            CaptureFormat::Pcap => {
+                header.extend_from_slice(&PCAP_MAGIC_NANOS.to_le_bytes());
+                header.extend_from_slice(&2u16.to_le_bytes());
+                header.extend_from_slice(&4u16.to_le_bytes());
+                header.extend_from_slice(&[0; 8]);
+                header.extend_from_slice(&u32::from(u16::MAX).to_le_bytes());
+                header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+            }
+            CaptureFormat::Pcapng => {
+                let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+                section.extend_from_slice(&1u16.to_le_bytes());
+                section.extend_from_slice(&0u16.to_le_bytes());
+                // Section length not known
+                section.extend_from_slice(&(-1i64).to_le_bytes());
+                pcapng_block(&mut header, PCAPNG_SECTION_HEADER, &section);
+
+                let mut interface = (LINKTYPE_ETHERNET as u16).to_le_bytes().to_vec();
+                interface.extend_from_slice(&[0; 2]);
+                interface.extend_from_slice(&0u32.to_le_bytes());
+                // if_tsresol of nanoseconds, then the end of options
+                interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0]);
+                interface.extend_from_slice(&[0; 4]);
+                pcapng_block(&mut header, PCAPNG_INTERFACE, &interface);
+            }
+        }
+
+        writer.write_all(&header)?;
+
+        Ok(CaptureWriter::appending(writer, format))
+    }
+
+    /// Writer for packets following a file header which is already written
+    pub fn appending(writer: W, format: CaptureFormat) -> CaptureWriter<W> {
+        CaptureWriter { writer, format }
+    }
+
+    /// Write a payload as a UDP datagram captured at `timestamp` ns since the epoch
+    pub fn write_packet(
+        &mut self,
+        timestamp: u64,
+        source: SocketAddrV4,
+        destination: SocketAddrV4,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let frame = udp_frame(source, destination, payload)?;
+        let mut bytes = Vec::with_capacity(frame.len() + 32);
+
+        match self.format {
+            CaptureFormat::Pcap => {
+                bytes.extend_from_slice(&((timestamp / 1_000_000_000) as u32).to_le_bytes());
+                bytes.extend_from_slice(&((timestamp % 1_000_000_000) as u32).to_le_bytes());
+                bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(&frame);
+            }
+            CaptureFormat::Pcapng => {
+                let mut packet = Vec::with_capacity(frame.len() + 20);
+                packet.extend_from_slice(&0u32.to_le_bytes());
+                packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+                packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+                packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                packet.extend_from_slice(&frame);
+                pcapng_block(&mut bytes, PCAPNG_ENHANCED_PACKET, &packet);
+            }
+        }
+
+        self.writer.write_all(&bytes)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+/// Length of a pcapng capture written by `CaptureWriter` up to the end of its last complete block.
+/// Anything after it is a block cut off by a killed recorder.
+pub fn pcapng_complete_length(path: &str) -> Result<u64, Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let length = file.get_ref().metadata()?.len();
+
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    if u32::from_le_bytes(header[0..4].try_into().unwrap()) != PCAPNG_SECTION_HEADER
+        || u32::from_le_bytes(header[8..12].try_into().unwrap()) != PCAPNG_BYTE_ORDER_MAGIC
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a little endian pcapng capture, cannot append to it", path),
+        ));
+    }
+
+    let mut end = 0;
+    loop {
+        file.seek(SeekFrom::Start(end))?;
+
+        let mut header = [0; 8];
+        if file.read_exact(&mut header).is_err() {
+            return Ok(end);
+        }
+
+        let block_length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+        if block_length < 12 || end + block_length > length {
+            return Ok(end);
+        }
+
+        end += block_length;
+    }
+}
+
+/// Append a pcapng block, padding its body to 32 bits
+fn pcapng_block(bytes: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+    let padding = body.len().next_multiple_of(4) - body.len();
+    let length = (12 + body.len() + padding) as u32;
+
+    bytes.extend_from_slice(&block_type.to_le_bytes());
+    bytes.extend_from_slice(&length.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes.extend_from_slice(&[0; 3][..padding]);
+    bytes.extend_from_slice(&length.to_le_bytes());
+}
+
+/// Ethernet frame of a UDP over IPv4 datagram.
+/// Multicast destinations get their multicast MAC, other addresses a locally administered MAC made from the IP.
+fn udp_frame(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let ip_length = u16::try_from(20 + 8 + payload.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} bytes do not fit a UDP datagram", payload.len())))?;
+
+    let mac = |ip: &Ipv4Addr| {
+        let [a, b, c, d] = ip.octets();
+        match ip.is_multicast() {
+            true => [0x01, 0x00, 0x5e, b & 0x7f, c, d],
+            false => [0x02, 0x00, a, b, c, d],
+        }
+    };
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&mac(destination.ip()));
+    frame.extend_from_slice(&mac(source.ip()));
+    frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+    let mut ip = [0; 20];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&ip_length.to_be_bytes());
+    ip[8] = 64;
+    ip[9] = IP_PROTOCOL_UDP;
+    ip[12..16].copy_from_slice(&source.ip().octets());
+    ip[16..20].copy_from_slice(&destination.ip().octets());
+    let checksum = ip_checksum(&ip);
+    ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+    frame.extend_from_slice(&ip);
+
+    // A zero UDP checksum means none over IPv4
+    frame.extend_from_slice(&source.port().to_be_bytes());
+    frame.extend_from_slice(&destination.port().to_be_bytes());
+    frame.extend_from_slice(&(ip_length - 20).to_be_bytes());
+    frame.extend_from_slice(&[0; 2]);
+    frame.extend_from_slice(payload);
+
+    Ok(frame)
+}
+
+fn ip_checksum(header: &[u8; 20]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SOURCE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 40000);
+    const GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 1, 2, 3), 5000);
+
+    fn read_all(capture: Vec<u8>) -> (CaptureReader<Cursor<Vec<u8>>>, Vec<Packet>) {
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        let mut packets = vec![];
+
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet);
+        }
+
+        (reader, packets)
+    }
+
+    /// Classic big endian pcap with microsecond timestamps of raw IPv4 frames
+    fn big_endian_pcap(frames: &[&[u8]]) -> Vec<u8> {
+        let mut capture = vec![];
+        capture.extend_from_slice(&PCAP_MAGIC_MICROS.to_be_bytes());
+        capture.extend_from_slice(&[0, 2, 0, 4]);
+        capture.extend_from_slice(&[0; 12]);
+        capture.extend_from_slice(&LINKTYPE_RAW.to_be_bytes());
+
+        for (i, frame) in frames.iter().enumerate() {
+            capture.extend_from_slice(&(1_700_000_000 + i as u32).to_be_bytes());
+            capture.extend_from_slice(&250_000u32.to_be_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            capture.extend_from_slice(frame);
+        }
+
+        capture
+    }
+
+    #[test]
+    fn round_trips_pcap_and_pcapng() {
+        for format in [CaptureFormat::Pcap, CaptureFormat::Pcapng] {
+            let mut writer = CaptureWriter::new(vec![], format).unwrap();
+            writer.write_packet(1_700_000_000_123_456_789, SOURCE, GROUP, b"hello").unwrap();
+            writer.write_packet(1_700_000_001_000_000_000, SOURCE, GROUP, &[]).unwrap();
+
+            let (reader, packets) = read_all(writer.get_ref().clone());
+            assert_eq!(reader.format, format);
+            assert_eq!(reader.skipped, 0);
+            let packets: Vec<_> =
+                packets.iter().map(|p| (p.timestamp, p.source, p.destination, p.payload.as_slice())).collect();
+            assert_eq!(
+                packets,
+                [
+                    (1_700_000_000_123_456_789, SOURCE, GROUP, b"hello".as_slice()),
+                    (1_700_000_001_000_000_000, SOURCE, GROUP, b"")
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_microsecond_raw_ip() {
+        let frame = udp_frame(SOURCE, GROUP, b"raw").unwrap();
+        let (_, packets) = read_all(big_endian_pcap(&[&frame[14..]]));
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].timestamp, 1_700_000_000_250_000_000);
+        assert_eq!((packets[0].source, packets[0].destination), (SOURCE, GROUP));
+        assert_eq!(packets[0].payload, b"raw");
+    }
+
+    #[test]
+    fn skips_other_protocols_and_fragments() {
+        let udp = udp_frame(SOURCE, GROUP, b"udp").unwrap()[14..].to_vec();
+        let mut tcp = udp.clone();
+        tcp[9] = 6;
+        let mut fragment = udp.clone();
+        fragment[6] = 0x20;
+
+        let (reader, packets) = read_all(big_endian_pcap(&[&tcp, &fragment, &udp]));
+        assert_eq!(reader.skipped, 2);
+        assert_eq!(packets.iter().map(|p| p.payload.as_slice()).collect::<Vec<_>>(), [b"udp"]);
+    }
+
+    #[test]
+    fn stops_at_packet_cut_off_by_end() {
+        let mut writer = CaptureWriter::new(vec![], CaptureFormat::Pcapng).unwrap();
+        writer.write_packet(1, SOURCE, GROUP, b"complete").unwrap();
+        writer.write_packet(2, SOURCE, GROUP, b"cut off").unwrap();
+
+        let mut capture = writer.get_ref().clone();
+        capture.truncate(capture.len() - 5);
+
+        let (_, packets) = read_all(capture);
+        assert_eq!(packets.iter().map(|p| p.payload.as_slice()).collect::<Vec<_>>(), [b"complete"]);
+    }
+
+    #[test]
+    fn resumes_growing_captures() {
+        for format in [CaptureFormat::Pcap, CaptureFormat::Pcapng] {
+            let mut writer = CaptureWriter::new(vec![], format).unwrap();
+            writer.write_packet(1, SOURCE, GROUP, b"first").unwrap();
+            writer.write_packet(2, SOURCE, GROUP, b"second").unwrap();
+            writer.write_packet(3, SOURCE, GROUP, b"third").unwrap();
+            let capture = writer.get_ref().clone();
+
+            // Cut off anywhere after the file header, then written to the end
+            for cut in 0..capture.len() {
+                let Ok(mut reader) = CaptureReader::new(Cursor::new(capture[..cut].to_vec())) else {
+                    continue;
+                };
+
+                let mut read = vec![];
+                while let Some(packet) = reader.next_packet().unwrap() {
+                    read.push(packet.payload);
+                }
+
+                reader.reader.get_mut().extend_from_slice(&capture[cut..]);
+                reader.resume().unwrap();
+                while let Some(packet) = reader.next_packet().unwrap() {
+                    read.push(packet.payload);
+                }
+
+                assert_eq!(read, [&b"first"[..], b"second", b"third"], "{:?} cut at {}", format, cut);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = CaptureReader::new(Cursor::new(b"RECORDER recording".to_vec())).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn writes_valid_ip_checksums() {
+        let frame = udp_frame(SOURCE, GROUP, b"checked").unwrap();
+        assert_eq!(ip_checksum(frame[14..34].try_into().unwrap()), 0);
+    }
+}
@@ -2,11 +2,22 @@ use bus::{Bus, BusReader};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::io::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::{fs, thread};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fs, process, thread};
 
-use crate::constants::BUF_SIZE;
+use crate::constants::BUS_SIZE;
+use crate::format::Compression;
+use crate::message::Message;
+
+/// Attempts to broadcast on a full bus before backing off with sleeps
+const FULL_BUS_SPINS: u32 = 16;
+/// First and longest sleep while the bus stays full, doubling in between
+const FULL_BUS_MIN_BACKOFF: Duration = Duration::from_micros(50);
+const FULL_BUS_MAX_BACKOFF: Duration = Duration::from_millis(1);
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
@@ -18,6 +29,40 @@ pub enum Mode {
     TcpProxy,
     File,
     Udp,
+    /// pcap or pcapng capture
+    Pcap,
+    /// Several recordings replayed in capture time order
+    Merge,
+    /// Hand written text fixture, see `fixture`
+    Fixture,
+}
+
+/// What a file output does when writing fails, for example because the disk is full
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OnWriteError {
+    /// Stop the recorder
+    #[default]
+    Fail,
+    /// Continue in `failover_path`, stop the recorder if that fails too
+    Failover,
+    /// Retry every `retry_interval_ms` until it succeeds, inputs block meanwhile
+    Retry,
+    /// Drop records which cannot be written and count them
+    Drop,
+}
+
+/// When a file output syncs its file to disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// Leave it to the operating system
+    #[default]
+    Never,
+    /// After a flush once `fsync_interval_ms` passed since the last sync, and when closing the file
+    Interval,
+    /// When closing the file on rollover
+    Rollover,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,6 +76,7 @@ pub struct Block {
     pub bind_ip: String,
     #[serde(default = "default_port")]
     pub bind_port: u16,
+    /// Path of the recording, a template with strftime tokens and placeholders, see `template`
     #[serde(default)]
     pub file_path: String,
     #[serde(default)]
@@ -45,13 +91,127 @@ pub struct Block {
     pub controlled_play: bool,
     #[serde(default = "default_speed")]
     pub speed_multiplier: f64,
+    /// Replay from the first record at or after this time of day or offset, see `WindowBound`
+    #[serde(default)]
+    pub play_start: String,
+    /// Stop or loop at the first record at or after this time of day or offset
+    #[serde(default)]
+    pub play_end: String,
+    /// Number of records to skip before replay starts
+    #[serde(default)]
+    pub play_start_record: u64,
+    /// Write an index entry every this many records, 0 disables it
+    #[serde(default = "default_index_records")]
+    pub index_records: u64,
+    /// Write an index entry every this many milliseconds of capture time, 0 disables it
+    #[serde(default = "default_index_interval_ms")]
+    pub index_interval_ms: u64,
+    /// Store a CRC-32 with every record of new recordings
+    #[serde(default = "default_checksums")]
+    pub checksums: bool,
+    /// Start a new output file at this time of day, `HH:MM[:SS]`, empty disables it
+    #[serde(default)]
+    pub rollover_time: String,
+    /// Timezone of `rollover_time` and file path times, `local`, `UTC` or an offset like `+02:00`
+    #[serde(default)]
+    pub rollover_timezone: String,
+    /// Start a new output file once it reaches this many megabytes, 0 disables it
+    #[serde(default)]
+    pub rollover_size_mb: u64,
+    /// Start a new output file after it has been open this many seconds, 0 disables it
+    #[serde(default)]
+    pub rollover_duration_s: u64,
+    /// Delete recordings of a file output older than this many days, 0 disables it
+    #[serde(default)]
+    pub retention_days: u64,
+    /// Delete the oldest recordings of a file output while they take up more than this, 0 disables it
+    #[serde(default)]
+    pub retention_max_gb: f64,
+    /// Compress recordings of a file output older than this many days, 0 disables it
+    #[serde(default)]
+    pub compress_after_days: u64,
+    /// How often retention runs
+    #[serde(default = "default_retention_interval_s")]
+    pub retention_interval_s: u64,
+    #[serde(default)]
+    pub on_write_error: OnWriteError,
+    /// Directory a file output continues in after failing over
+    #[serde(default)]
+    pub failover_path: String,
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+    /// File the status of a file output is written to as JSON, empty disables it
+    #[serde(default)]
+    pub status_file: String,
+    /// Write buffered records of a file output at least this often, 0 writes every record right away.
+    /// Compressed outputs write at most every 100 ms, so blocks hold enough records to compress.
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// Write buffered records of a file output once this many bytes are buffered
+    #[serde(default = "default_max_buffered_bytes")]
+    pub max_buffered_bytes: usize,
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    /// Interval of `FsyncPolicy::Interval`, 0 syncs on every flush
+    #[serde(default = "default_fsync_interval_ms")]
+    pub fsync_interval_ms: u64,
+    /// Recordings a merge input replays, each a template like `file_path`
+    #[serde(default)]
+    pub file_paths: Vec<String>,
+    /// Compress new recordings of a file output in blocks, one per flush
+    #[serde(default)]
+    pub compression: Compression,
+    /// zstd compression level, 0 is zstd's default
+    #[serde(default)]
+    pub compression_level: i32,
     pub mode: Mode,
 }
 
+impl Block {
+    /// Group or address and port of `source_ip` and `source_port`, None if the address is unspecified or invalid
+    pub fn source_address(&self) -> Option<SocketAddr> {
+        let ip: IpAddr = self.source_ip.parse().ok()?;
+
+        (!ip.is_unspecified()).then_some(SocketAddr::new(ip, self.source_port))
+    }
+}
+
 fn default_speed() -> f64 {
     1.0
 }
 
+fn default_index_records() -> u64 {
+    10000
+}
+
+fn default_index_interval_ms() -> u64 {
+    1000
+}
+
+fn default_checksums() -> bool {
+    true
+}
+
+fn default_retention_interval_s() -> u64 {
+    600
+}
+
+fn default_retry_interval_ms() -> u64 {
+    1000
+}
+
+fn default_flush_interval_ms() -> u64 {
+    100
+}
+
+fn default_max_buffered_bytes() -> usize {
+    65536
+}
+
+fn default_fsync_interval_ms() -> u64 {
+    1000
+}
+
 fn default_ip() -> String {
     "0.0.0.0".to_string()
 }
@@ -68,19 +228,91 @@ pub struct Settings {
 }
 
 pub struct Recorder {
-    bus: Arc<Mutex<Bus<([u8; BUF_SIZE], u32)>>>,
-    output_bus: Vec<Arc<Mutex<BusReader<([u8; BUF_SIZE], u32)>>>>,
+    /// None once the recorder is closed
+    bus: Arc<Mutex<Option<Bus<Message>>>>,
+    output_bus: Vec<Arc<Mutex<BusReader<Message>>>>,
     input: Vec<(Block, Arc<dyn Input>)>,
     output: Vec<(Block, Arc<dyn Output>)>,
 }
 
+/// Cloneable writing end of the shared input bus.
+/// Every input thread gets its own handle, the lock is only held for a single broadcast attempt
+/// so one input can never starve the others.
+/// Clones share the input id and counter, so an input may hand them to its own worker threads.
+#[derive(Clone)]
+pub struct BusWriter {
+    bus: Arc<Mutex<Option<Bus<Message>>>>,
+    input_id: usize,
+    seq: Arc<AtomicU64>,
+}
+
+impl BusWriter {
+    pub fn new(bus: Arc<Mutex<Option<Bus<Message>>>>, input_id: usize) -> BusWriter {
+        BusWriter {
+            bus,
+            input_id,
+            seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Copy a freshly captured buffer into an exactly sized message envelope and broadcast it.
+    /// Should be called right after the packet is read, as the capture time is taken here.
+    /// The input is free to reuse `data` once this returns.
+    pub fn send(&self, data: &[u8], source: Option<SocketAddr>) {
+        self.send_to(data, source, None);
+    }
+
+    /// Like `send`, for replayed packets which know where they were originally sent to
+    pub fn send_to(&self, data: &[u8], source: Option<SocketAddr>, destination: Option<SocketAddr>) {
+        let message = Message {
+            wall_time: SystemTime::now(),
+            mono_time: Instant::now(),
+            input_id: self.input_id,
+            source,
+            destination,
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            data: Arc::from(data),
+        };
+
+        self.broadcast(message);
+    }
+
+    /// Broadcast a message to all outputs, blocking while the bus is full.
+    /// Messages sent after the recorder was closed are dropped.
+    pub fn broadcast(&self, mut val: Message) {
+        let mut attempts = 0;
+
+        loop {
+            let result = match self.bus.lock().unwrap().as_mut() {
+                Some(bus) => bus.try_broadcast(val),
+                None => return,
+            };
+
+            match result {
+                Ok(()) => return,
+                Err(v) => val = v,
+            }
+
+            // Bus is full, release the lock and let outputs catch up.
+            // A stalled output can keep it full for long, so back off instead of spinning.
+            attempts += 1;
+            if attempts <= FULL_BUS_SPINS {
+                thread::yield_now();
+            } else {
+                let doublings = (attempts - FULL_BUS_SPINS).min(5);
+                thread::sleep((FULL_BUS_MIN_BACKOFF * 2u32.pow(doublings)).min(FULL_BUS_MAX_BACKOFF));
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum AdapterType {
     Input(Arc<dyn Input>),
     Output(Arc<dyn Output>),
 }
 
-impl<'a> Recorder {
+impl Recorder {
     pub fn new(config_path: String, adapters: Vec<(Mode, AdapterType)>) -> Recorder {
         let config = fs::read_to_string(config_path).unwrap();
         let settings: Settings = serde_json::from_str(&config).unwrap();
@@ -97,7 +329,7 @@ impl<'a> Recorder {
                         AdapterType::Input(_) => mode == &i.mode,
                         _ => false,
                     })
-                    .expect(format!("Cannot find input adapter for {:?}", i.mode).as_str());
+                    .unwrap_or_else(|| panic!("Cannot find input adapter for {:?}", i.mode));
 
                 if let AdapterType::Input(adapter) = adapter {
                     Some((i, adapter.clone()))
@@ -119,7 +351,7 @@ impl<'a> Recorder {
                         AdapterType::Output(_) => mode == &i.mode,
                         _ => false,
                     })
-                    .expect(format!("Cannot find output adapter for {:?}", i.mode).as_str());
+                    .unwrap_or_else(|| panic!("Cannot find output adapter for {:?}", i.mode));
 
                 if let AdapterType::Output(adapter) = adapter {
                     Some((i, adapter.clone()))
@@ -129,17 +361,17 @@ impl<'a> Recorder {
             })
             .collect();
 
-        if input_adapters.len() == 0 {
+        if input_adapters.is_empty() {
             panic!("Error, No input adapters found");
         }
 
         // if no output adapters and no proxy adapter, then error
-        if output_adapters.len() == 0 && !input_adapters.iter().any(|(block, _)| block.mode == Mode::TcpProxy) {
+        if output_adapters.is_empty() && !input_adapters.iter().any(|(block, _)| block.mode == Mode::TcpProxy) {
             panic!("Error, No output adapters found");
         }
 
         // Create a bus of buffer for input
-        let mut bus = Bus::<([u8; BUF_SIZE], u32)>::new(1000);
+        let mut bus = Bus::<Message>::new(BUS_SIZE);
         // Create a output vector of output bus
         let mut output_bus = vec![];
 
@@ -160,61 +392,99 @@ impl<'a> Recorder {
         }
 
         Recorder {
-            bus: Arc::new(Mutex::new(bus)),
+            bus: Arc::new(Mutex::new(Some(bus))),
             output_bus,
             input: input_adapters,
             output: output_adapters,
         }
     }
 
-    /// Read function spawns a reader in a thread and returns handle to the thread
-    pub fn read(&self) -> JoinHandle<()> {
+    /// Read function spawns one reader thread per input adapter and returns handles to the threads
+    /// along with the block each thread is reading from. Every thread reports how its input stopped.
+    pub fn read(&self) -> Vec<(Block, JoinHandle<Result<(), Error>>)> {
         let inputs = self.input.clone();
-        let bus = self.bus.clone();
 
-        thread::spawn(move || {
-            let mut bus = bus.lock().unwrap();
+        inputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, (source, input))| {
+                let channel = BusWriter::new(self.bus.clone(), i);
+                let block = source.clone();
+
+                let handle = thread::Builder::new()
+                    .name(format!("input-{}-{:?}", i, source.mode))
+                    .spawn(move || {
+                        let mode = source.mode.clone();
+                        let result = input.read(source, &channel);
 
-            for (source, input) in inputs {
-                input.read(source, &mut bus).unwrap();
-            }
-        })
+                        match &result {
+                            Ok(()) => println!("Input {:?} stopped", mode),
+                            Err(e) => println!("Input {:?} failed: {}", mode, e),
+                        }
+
+                        result
+                    })
+                    .unwrap();
+
+                (block, handle)
+            })
+            .collect()
     }
 
-    /// Write function spawns n threads for n output adapters and returns immediately
-    pub fn write(&self) {
+    /// Write function spawns n threads for n output adapters and returns handles to the threads
+    /// along with the block each thread is writing to. Outputs return once the recorder is closed
+    /// and they wrote everything left on the bus.
+    pub fn write(&self) -> Vec<(Block, JoinHandle<()>)> {
         let outputs = self.output.clone();
+        let inputs: Vec<Block> = self.input.iter().map(|(block, _)| block.clone()).collect();
 
-        let mut i = 0;
+        outputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, (source, output))| {
+                let output_bus = self.output_bus.get(i).unwrap().clone();
+                let inputs = inputs.clone();
+                let block = source.clone();
 
-        for (source, output) in outputs {
-            let output_bus = self.output_bus.get(i).unwrap().clone();
+                let handle = thread::Builder::new()
+                    .name(format!("output-{}-{:?}", i, source.mode))
+                    .spawn(move || {
+                        let mut output_bus = output_bus.lock().unwrap();
+                        let mode = source.mode.clone();
 
-            i += 1;
+                        // A dead output would stop draining the bus and block every input,
+                        // so stop the whole recorder instead
+                        if let Err(e) = output.write(source, &inputs, &mut output_bus) {
+                            println!("Output {:?} failed: {}, stopping recorder", mode, e);
+                            process::exit(1);
+                        }
+                    })
+                    .unwrap();
 
-            thread::spawn(move || {
-                let mut output_bus = output_bus.lock().unwrap();
+                (block, handle)
+            })
+            .collect()
+    }
 
-                output.write(source, &mut output_bus).unwrap();
-            });
-        }
+    /// Close the bus once every input stopped, outputs write what is left on it and return
+    pub fn close(&self) {
+        self.bus.lock().unwrap().take();
     }
 }
 
 pub trait Input: Send + Sync + Debug {
     /// This function should read from the source depending on the implementation and write it to channel.
     /// Should read in blocking mode.
-    /// Must only return in case of error.
-    fn read(&self, block: Block, channel: &mut Bus<([u8; BUF_SIZE], u32)>) -> Result<(), Error>;
+    /// Returns once the source ended, or in case of error.
+    /// Runs on its own thread, concurrently with other inputs sharing the same channel.
+    fn read(&self, block: Block, channel: &BusWriter) -> Result<(), Error>;
 }
 
 pub trait Output: Send + Sync + Debug {
     /// This function should read from the channel and write it to source depending on the implementation.
     /// Should write in blocking mode.
-    /// Must only return in case of error.
-    fn write(
-        &self,
-        block: Block,
-        channel: &mut BusReader<([u8; BUF_SIZE], u32)>,
-    ) -> Result<(), Error>;
+    /// Must return once the channel is disconnected and drained, after flushing and syncing what it wrote,
+    /// otherwise only in case of error.
+    /// `inputs` are the input blocks, indexed by `Message::input_id`.
+    fn write(&self, block: Block, inputs: &[Block], channel: &mut BusReader<Message>) -> Result<(), Error>;
 }
@@ -0,0 +1,504 @@
+use std::{
+    fmt::{self, Display},
+    hint,
+    io::{stdin, Error, ErrorKind},
+    net::SocketAddr,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+
+use crate::{
+    format::Record,
+    recorder::{Block, BusWriter},
+};
+
+/// Sleeping is only accurate to a few hundred microseconds, the rest of the wait is spun
+const SPIN_THRESHOLD: Duration = Duration::from_micros(500);
+/// How often stats are printed while replaying
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Upper bounds of the lateness histogram buckets
+const LATENESS_BUCKETS: [Duration; 5] = [
+    Duration::from_micros(1),
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+];
+
+/// Lateness of sends compared to their scheduled time
+#[derive(Debug, Default, Clone)]
+pub struct ReplayStats {
+    pub sent: u64,
+    pub total_lateness: Duration,
+    pub max_lateness: Duration,
+    /// Sends per lateness bucket, the last bucket holds everything above the largest bound
+    pub buckets: [u64; LATENESS_BUCKETS.len() + 1],
+}
+
+impl ReplayStats {
+    fn add(&mut self, lateness: Duration) {
+        self.sent += 1;
+        self.total_lateness += lateness;
+        self.max_lateness = self.max_lateness.max(lateness);
+
+        let bucket = LATENESS_BUCKETS
+            .iter()
+            .position(|bound| lateness < *bound)
+            .unwrap_or(LATENESS_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn mean_lateness(&self) -> Duration {
+        if self.sent == 0 {
+            return Duration::ZERO;
+        }
+
+        self.total_lateness / self.sent as u32
+    }
+}
+
+impl Display for ReplayStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {}, late by mean {:?} max {:?}, ",
+            self.sent,
+            self.mean_lateness(),
+            self.max_lateness
+        )?;
+
+        for (i, count) in self.buckets.iter().enumerate() {
+            match LATENESS_BUCKETS.get(i) {
+                Some(bound) => write!(f, "<{:?}: {} ", bound, count)?,
+                None => write!(f, ">={:?}: {}", LATENESS_BUCKETS[i - 1], count)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Paces timed replay against each packet's absolute offset from replay start,
+/// so sleep overshoot and time spent reading and broadcasting do not add up over a long replay
+#[derive(Debug)]
+pub struct ReplayScheduler {
+    name: String,
+    speed_multiplier: f64,
+    start: Instant,
+    /// Scheduled offset of the next send from start
+    offset: Duration,
+    last_report: Instant,
+    pub stats: ReplayStats,
+}
+
+impl ReplayScheduler {
+    pub fn new(name: &str, speed_multiplier: f64) -> ReplayScheduler {
+        ReplayScheduler {
+            name: name.to_string(),
+            speed_multiplier,
+            start: Instant::now(),
+            offset: Duration::ZERO,
+            last_report: Instant::now(),
+            stats: ReplayStats::default(),
+        }
+    }
+
+    /// Restart the schedule from now, after replay was paused or looped
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.offset = Duration::ZERO;
+    }
+
+    /// Block until the packet recorded `delta` ns after the previous one is due.
+    /// Returns how late the send is.
+    pub fn wait(&mut self, delta: u64) -> Duration {
+        self.offset += Duration::from_nanos((delta as f64 * self.speed_multiplier) as u64);
+        let target = self.start + self.offset;
+
+        let now = Instant::now();
+        if target > now + SPIN_THRESHOLD {
+            thread::sleep(target - now - SPIN_THRESHOLD);
+        }
+
+        while Instant::now() < target {
+            hint::spin_loop();
+        }
+
+        let lateness = Instant::now().saturating_duration_since(target);
+        self.stats.add(lateness);
+
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+
+        lateness
+    }
+
+    /// Print stats gathered so far
+    pub fn report(&mut self) {
+        self.last_report = Instant::now();
+
+        println!("Replay {}: {}", self.name, self.stats);
+    }
+}
+
+/// Start or end of a replay window.
+/// Written either as a local time of day, `09:15` or `09:15:30.250`,
+/// or as an offset from the first record, `+90s`, `+1500ms`, `+5m`, `+1h`, unit defaults to seconds.
+#[derive(Debug, Clone, Copy)]
+pub enum WindowBound {
+    TimeOfDay(NaiveTime),
+    Offset(Duration),
+}
+
+impl FromStr for WindowBound {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<WindowBound, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid replay window bound {:?}", s));
+
+        if let Some(offset) = s.strip_prefix('+') {
+            let split = offset.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(offset.len());
+            let (value, unit) = offset.split_at(split);
+            let value: f64 = value.parse().map_err(|_| invalid())?;
+
+            let seconds = match unit {
+                "ms" => value / 1000.0,
+                "" | "s" => value,
+                "m" => value * 60.0,
+                "h" => value * 3600.0,
+                _ => return Err(invalid()),
+            };
+
+            return Duration::try_from_secs_f64(seconds)
+                .map(WindowBound::Offset)
+                .map_err(|_| invalid());
+        }
+
+        NaiveTime::parse_from_str(s, "%H:%M:%S%.f")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+            .map(WindowBound::TimeOfDay)
+            .map_err(|_| invalid())
+    }
+}
+
+impl WindowBound {
+    /// Parse an optional bound from block settings, empty means unbounded
+    pub fn parse_setting(setting: &str) -> Result<Option<WindowBound>, Error> {
+        if setting.is_empty() {
+            return Ok(None);
+        }
+
+        setting.parse().map(Some)
+    }
+
+    /// Find where this bound lies in a recording, given its first record.
+    /// Times of day are taken on the local date of the first record.
+    pub fn resolve(&self, first: &Record) -> Result<WindowPosition, Error> {
+        match self {
+            WindowBound::Offset(offset) => Ok(WindowPosition::Elapsed(offset.as_nanos() as u64)),
+            WindowBound::TimeOfDay(time) => {
+                if first.timestamp == 0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "recording has no capture timestamps, use an offset for the replay window",
+                    ));
+                }
+
+                let first_time = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(first.timestamp));
+                let bound = Local
+                    .from_local_datetime(&first_time.date_naive().and_time(*time))
+                    .earliest()
+                    .and_then(|bound| bound.timestamp_nanos_opt())
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} does not exist on {}", time, first_time.date_naive())))?;
+
+                Ok(WindowPosition::Timestamp(bound.max(0) as u64))
+            }
+        }
+    }
+}
+
+/// Window bound resolved against a recording
+#[derive(Debug, Clone, Copy)]
+pub enum WindowPosition {
+    /// Capture timestamp in nanoseconds since unix epoch
+    Timestamp(u64),
+    /// Nanoseconds since the first record
+    Elapsed(u64),
+}
+
+impl WindowPosition {
+    /// Whether the record lies at or after this position
+    pub fn reached(&self, record: &Record) -> bool {
+        match self {
+            WindowPosition::Timestamp(timestamp) => record.timestamp >= *timestamp,
+            WindowPosition::Elapsed(elapsed) => record.elapsed >= *elapsed,
+        }
+    }
+}
+
+/// Record of a replay source with the addresses it is sent with, see `Message`
+#[derive(Debug)]
+pub struct Replayed {
+    pub record: Record,
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+/// Recording, capture or fixture an input replays, see `replay`.
+/// Records need `delta` and `elapsed` filled in, `timestamp` only for time of day windows.
+pub trait ReplaySource {
+    /// Next record, None at the end.
+    /// Called again after the end it returns records appended since, if the source can grow.
+    fn next(&mut self) -> Result<Option<Replayed>, Error>;
+
+    /// Start over from the first record
+    fn rewind(&mut self) -> Result<(), Error>;
+
+    /// Jump close to the replay window start, returning how many records were skipped.
+    /// Sources without an index read up to it instead.
+    fn seek(&mut self, _start_record: Option<u64>, _start: Option<&WindowPosition>) -> Result<u64, Error> {
+        Ok(0)
+    }
+}
+
+/// Replay `source` the way `block` asks: window, timing, speed, stepping from stdin and looping.
+/// At the end the source is looped or polled for appended records, replay only returns at the window end.
+pub fn replay(block: &Block, name: &str, source: &mut impl ReplaySource, channel: &BusWriter) -> Result<(), Error> {
+    let play_start = WindowBound::parse_setting(&block.play_start)?;
+    let play_end = WindowBound::parse_setting(&block.play_end)?;
+
+    // Bounds of the same kind compare before any record is read, others only once resolved
+    let empty = match (play_start, play_end) {
+        (Some(WindowBound::Offset(start)), Some(WindowBound::Offset(end))) => start >= end,
+        (Some(WindowBound::TimeOfDay(start)), Some(WindowBound::TimeOfDay(end))) => start >= end,
+        _ => false,
+    };
+    if empty {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("replay window {} to {} of {} is empty", block.play_start, block.play_end, name),
+        ));
+    }
+
+    let mut count: i32 = 0;
+    let mut scheduler = ReplayScheduler::new(name, block.speed_multiplier);
+
+    // Replay window state, resolved against the first record after every rewind
+    let mut index: u64 = 0;
+    let mut start = None;
+    let mut end = None;
+    let mut in_window = false;
+    // Whether anything was sent since the last rewind
+    let mut sent = false;
+
+    loop {
+        let Some(Replayed {
+            record,
+            source: sender,
+            destination,
+        }) = source.next()?
+        else {
+            if block.play_timed {
+                scheduler.report();
+            }
+
+            if block.play_loop {
+                wait_if_none_sent(sent, name);
+                source.rewind()?;
+                index = 0;
+                in_window = false;
+                sent = false;
+            } else {
+                println!("{} ended, waiting for changes", name);
+                thread::sleep(Duration::from_secs(2));
+            }
+
+            // Schedule restarts with the next record, don't try to catch up the wait
+            scheduler.reset();
+            continue;
+        };
+
+        if index == 0 {
+            start = play_start.map(|bound| bound.resolve(&record)).transpose()?;
+            end = play_end.map(|bound| bound.resolve(&record)).transpose()?;
+
+            let start_record = (block.play_start_record > 0).then_some(block.play_start_record);
+            let skipped = source.seek(start_record, start.as_ref())?;
+            if skipped > 0 {
+                index = skipped;
+                continue;
+            }
+        }
+        index += 1;
+
+        // Skip records before the window without sleeping through them
+        let first_in_window = !in_window;
+        if !in_window {
+            if index <= block.play_start_record || start.is_some_and(|start| !start.reached(&record)) {
+                continue;
+            }
+
+            in_window = true;
+        }
+
+        if end.is_some_and(|end| end.reached(&record)) {
+            if block.play_timed {
+                scheduler.report();
+            }
+
+            if !block.play_loop {
+                println!("Reached end of replay window for {}", name);
+                return Ok(());
+            }
+
+            wait_if_none_sent(sent, name);
+            source.rewind()?;
+            index = 0;
+            in_window = false;
+            sent = false;
+            continue;
+        }
+
+        // enter / 0 = 1 packet
+        // 2 = 2 packets
+        // -1 = infinite
+        if block.controlled_play {
+            if count == 0 {
+                let mut count_str = String::new();
+                println!("Waiting for key press");
+
+                stdin().read_line(&mut count_str)?;
+
+                count = count_str.trim().parse().unwrap_or(1) - 1;
+                scheduler.reset();
+            } else if count > 0 {
+                count -= 1;
+            }
+        }
+
+        if block.play_timed {
+            // Gap to the first record of the window includes everything that was skipped
+            if first_in_window {
+                scheduler.reset();
+            } else {
+                scheduler.wait(record.delta);
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        println!("Reading {} bytes from {:?}", record.data.len(), block.mode);
+        channel.send_to(&record.data, sender, destination);
+        sent = true;
+    }
+}
+
+/// Looping over a window without records would spin, wait for the source to change instead
+fn wait_if_none_sent(sent: bool, name: &str) {
+    if !sent {
+        println!("Nothing of {} in the replay window, waiting for changes", name);
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bus::Bus;
+    use serde_json::json;
+
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    /// One record per second, without capture timestamps like a fixture
+    struct Seconds {
+        count: u64,
+        next: u64,
+        /// Rewinds left until rewinding fails, to end looping replays
+        rewinds: u64,
+    }
+
+    impl ReplaySource for Seconds {
+        fn next(&mut self) -> Result<Option<Replayed>, Error> {
+            if self.next == self.count {
+                return Ok(None);
+            }
+
+            let record = Record {
+                timestamp: 0,
+                monotonic: self.next * SECOND,
+                delta: if self.next == 0 { 0 } else { SECOND },
+                elapsed: self.next * SECOND,
+                input: 0,
+                data: vec![self.next as u8],
+            };
+            self.next += 1;
+
+            Ok(Some(Replayed {
+                record,
+                source: None,
+                destination: None,
+            }))
+        }
+
+        fn rewind(&mut self) -> Result<(), Error> {
+            if self.rewinds == 0 {
+                return Err(Error::other("no rewinds left"));
+            }
+
+            self.rewinds -= 1;
+            self.next = 0;
+            Ok(())
+        }
+    }
+
+    fn replayed(settings: serde_json::Value) -> Result<Vec<u8>, Error> {
+        let block: Block = serde_json::from_value(settings)?;
+        let mut bus = Bus::new(100);
+        let mut reader = bus.add_rx();
+        let channel = BusWriter::new(Arc::new(Mutex::new(Some(bus))), 0);
+
+        replay(&block, "test", &mut Seconds { count: 10, next: 0, rewinds: 0 }, &channel)?;
+        drop(channel);
+
+        Ok(reader.iter().map(|message| message.data[0]).collect())
+    }
+
+    #[test]
+    fn replays_window_of_any_source() {
+        let sent = replayed(json!({ "mode": "fixture", "play_start": "+2s", "play_end": "+5s" })).unwrap();
+        assert_eq!(sent, [2, 3, 4]);
+
+        let sent = replayed(json!({ "mode": "fixture", "play_start_record": 3, "play_end": "+1500ms" })).unwrap();
+        assert!(sent.is_empty());
+
+        let sent = replayed(json!({ "mode": "fixture", "play_start_record": 6, "play_end": "+8s" })).unwrap();
+        assert_eq!(sent, [6, 7]);
+
+        // Sources without capture timestamps can only be windowed by offset
+        assert!(replayed(json!({ "mode": "fixture", "play_end": "09:00" })).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_windows_and_waits_when_looping_over_nothing() {
+        for (start, end) in [("+5s", "+2s"), ("+2s", "+2s"), ("10:00", "09:00")] {
+            let error = replayed(json!({ "mode": "fixture", "play_start": start, "play_end": end })).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput, "{} to {}", start, end);
+        }
+
+        // Window past the end, every pass sends nothing
+        let block: Block = serde_json::from_value(json!({ "mode": "fixture", "play_start": "+20s", "play_loop": true }))
+            .unwrap();
+        let channel = BusWriter::new(Arc::new(Mutex::new(Some(Bus::new(100)))), 0);
+
+        let start = Instant::now();
+        let error = replay(&block, "test", &mut Seconds { count: 10, next: 0, rewinds: 0 }, &channel).unwrap_err();
+        assert_eq!(error.to_string(), "no rewinds left");
+        assert!(start.elapsed() >= Duration::from_secs(2), "{:?}", start.elapsed());
+    }
+}
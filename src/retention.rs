@@ -0,0 +1,636 @@
+//! Retention of the recordings of a file output.
+//!
+//! Applies to files the output's path template can expand to, with their rollover sequence
+//! number, index and quarantined partial records. The scan starts in the
+//! template's literal directory and only descends into directories the template has tokens for.
+//! Templates need a literal directory or file name prefix before their first token, so retention
+//! never starts from the filesystem root or the working directory on tokens alone.
+//! The file currently being written and its index are never touched.
+//! Recordings are compressed in place into zstd compressed blocks, the way `convert` does,
+//! so they stay readable by replay and every tool.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    format::{Compression, FileHeader, RecordReader},
+    index::{index_path, Index},
+    recorder::{Block, Mode},
+    tools::convert::convert,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 3600);
+/// Extension of the compressed copy of a recording while it is written
+const COMPRESSING_EXTENSION: &str = "compressing";
+/// Extensions of files belonging to a recording
+const SIDECAR_EXTENSIONS: [&str; 3] = ["idx", "partial", COMPRESSING_EXTENSION];
+/// strftime specifiers which expand to a number
+const NUMERIC_SPECIFIERS: &str = "YCymdHIMSjsfuwUWGgV";
+
+/// Piece of a path component of a template
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// One or more ASCII digits
+    Digits,
+    /// Any text, possibly empty
+    Any,
+}
+
+/// Names one path component of a template can expand to
+#[derive(Debug, Clone, PartialEq)]
+struct Pattern(Vec<Part>);
+
+impl Pattern {
+    /// Pattern of a path component of a template, see `template::expand`
+    fn parse(component: &str) -> Pattern {
+        let mut parts = vec![];
+        let mut rest = component;
+
+        while let Some(c) = rest.chars().next() {
+            if c == '$' {
+                let dash = || Part::Literal("-".to_string());
+                let placeholder = [
+                    ("date", vec![Part::Digits, dash(), Part::Digits, dash(), Part::Digits]),
+                    ("hostname", vec![Part::Any]),
+                    ("mode", vec![Part::Any]),
+                    ("source_ip", vec![Part::Any]),
+                    ("source_port", vec![Part::Digits]),
+                    ("pid", vec![Part::Digits]),
+                    ("seq", vec![Part::Digits]),
+                ]
+                .into_iter()
+                .find(|(name, _)| rest[1..].starts_with(name));
+
+                if let Some((name, expanded)) = placeholder {
+                    parts.extend(expanded);
+                    rest = &rest[1 + name.len()..];
+                    continue;
+                }
+            }
+
+            if c == '%' {
+                // Flags, width and precision, then the specifier
+                let specifier = rest[1..].trim_start_matches(|c: char| "-_0^#.:".contains(c) || c.is_ascii_digit());
+
+                let Some(c) = specifier.chars().next() else {
+                    break;
+                };
+
+                parts.push(match c {
+                    '%' => Part::Literal("%".to_string()),
+                    c if NUMERIC_SPECIFIERS.contains(c) => Part::Digits,
+                    _ => Part::Any,
+                });
+                rest = &specifier[c.len_utf8()..];
+                continue;
+            }
+
+            match parts.last_mut() {
+                Some(Part::Literal(literal)) => literal.push(c),
+                _ => parts.push(Part::Literal(c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+
+        Pattern(parts)
+    }
+
+    fn is_literal(&self) -> bool {
+        self.0.iter().all(|part| matches!(part, Part::Literal(_)))
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        matches_parts(&self.0, name)
+    }
+}
+
+fn matches_parts(parts: &[Part], name: &str) -> bool {
+    let Some((part, rest)) = parts.split_first() else {
+        return name.is_empty();
+    };
+
+    match part {
+        Part::Literal(literal) => name.strip_prefix(literal.as_str()).is_some_and(|name| matches_parts(rest, name)),
+        Part::Digits => {
+            let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (1..=digits).any(|end| matches_parts(rest, &name[end..]))
+        }
+        Part::Any => name.char_indices().map(|(i, _)| i).chain([name.len()]).any(|i| matches_parts(rest, &name[i..])),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Retention {
+    /// Literal directory of the template, where the scan starts
+    root: PathBuf,
+    /// Patterns of the template's path components below `root`, the file name last
+    components: Vec<Pattern>,
+    keep: Option<Duration>,
+    max_bytes: Option<u64>,
+    compress_after: Option<Duration>,
+    /// Index settings of the output, for indexes rebuilt after compressing
+    index_records: u64,
+    index_interval_ms: u64,
+    interval: Duration,
+}
+
+/// A file found by retention
+#[derive(Debug)]
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Retention {
+    /// Retention settings of a file output, None if none is configured
+    pub fn from_block(block: &Block) -> Result<Option<Retention>, Error> {
+        if block.retention_days == 0 && block.retention_max_gb <= 0.0 && block.compress_after_days == 0 {
+            return Ok(None);
+        }
+
+        let (root, components) = Retention::anchor(&block.file_path)?;
+        let days = |days: u64| (days > 0).then(|| DAY * days as u32);
+
+        // Raw and pcapng output has no recording format to compress into
+        let records = block.no_headers && block.mode != Mode::Pcap;
+        if block.compress_after_days > 0 && !records {
+            println!("Not compressing output {}, it does not write recordings", block.file_path);
+        }
+
+        Ok(Some(Retention {
+            root,
+            components,
+            keep: days(block.retention_days),
+            max_bytes: (block.retention_max_gb > 0.0).then_some((block.retention_max_gb * 1024.0 * 1024.0 * 1024.0) as u64),
+            compress_after: days(block.compress_after_days).filter(|_| records),
+            index_records: block.index_records,
+            index_interval_ms: block.index_interval_ms,
+            interval: Duration::from_secs(block.retention_interval_s.max(1)),
+        }))
+    }
+
+    /// Literal directory of a template and patterns of the path components below it
+    fn anchor(template: &str) -> Result<(PathBuf, Vec<Pattern>), Error> {
+        let fixed = template.split(['%', '$']).next().unwrap_or_default();
+        if fixed.trim_start_matches("./").trim_start_matches('/').is_empty() && fixed.len() < template.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("retention needs a literal directory or file name prefix in file path {:?}", template),
+            ));
+        }
+
+        let mut components: Vec<Pattern> = template.split('/').map(Pattern::parse).collect();
+        let literal = components[..components.len() - 1]
+            .iter()
+            .take_while(|pattern| pattern.is_literal())
+            .count();
+
+        let root = match (literal, template.starts_with('/')) {
+            (0, _) => PathBuf::from("."),
+            (1, true) => PathBuf::from("/"),
+            (literal, _) => PathBuf::from(template.split('/').take(literal).collect::<Vec<_>>().join("/")),
+        };
+
+        Ok((root, components.split_off(literal)))
+    }
+
+    /// Run retention every interval on a background thread.
+    /// `current` holds the path of the file being written.
+    pub fn spawn(self, current: Arc<Mutex<String>>) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(format!("retention-{}", self.root.display()))
+            .spawn(move || {
+                println!(
+                    "Retention of {}: keep {:?}, max {:?} bytes, compress after {:?}",
+                    self.root.display(),
+                    self.keep,
+                    self.max_bytes,
+                    self.compress_after
+                );
+
+                loop {
+                    if let Err(e) = self.run(&current) {
+                        println!("Retention of {} failed: {}", self.root.display(), e);
+                    }
+
+                    thread::sleep(self.interval);
+                }
+            })
+            .unwrap()
+    }
+
+    /// Delete by age, then compress, then delete oldest first until under the size limit.
+    /// Files which fail are reported and skipped, only failing to look for files fails the run.
+    pub fn run(&self, current: &Mutex<String>) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let age = |candidate: &Candidate| now.duration_since(candidate.modified).unwrap_or_default();
+
+        let mut candidates = self.candidates(current)?;
+
+        if let Some(keep) = self.keep {
+            for candidate in candidates.iter().filter(|candidate| age(candidate) >= keep) {
+                if let Err(e) = self.delete(candidate, "older than retention", current) {
+                    println!("Retention could not delete {}: {}", candidate.path.display(), e);
+                }
+            }
+
+            candidates.retain(|candidate| age(candidate) < keep);
+        }
+
+        if let Some(compress_after) = self.compress_after {
+            for candidate in &candidates {
+                if age(candidate) >= compress_after && self.is_recording(&candidate.path) {
+                    if let Err(e) = self.compress(candidate, current) {
+                        println!("Retention could not compress {}: {}", candidate.path.display(), e);
+                    }
+                }
+            }
+
+            candidates = self.candidates(current)?;
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            // The open file counts towards the limit but cannot be deleted
+            let mut total = candidates.iter().map(|candidate| candidate.size).sum::<u64>()
+                + fs::metadata(&*current.lock().unwrap()).map(|metadata| metadata.len()).unwrap_or_default();
+
+            candidates.sort_by_key(|candidate| candidate.modified);
+
+            for candidate in candidates {
+                if total <= max_bytes {
+                    break;
+                }
+
+                match self.delete(&candidate, "over retention size", current) {
+                    Ok(()) => total -= candidate.size,
+                    Err(e) => println!("Retention could not delete {}: {}", candidate.path.display(), e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Files retention may act on, without the open file and its index
+    fn candidates(&self, current: &Mutex<String>) -> Result<Vec<Candidate>, Error> {
+        let mut found = vec![];
+        let mut directories = vec![(self.root.clone(), 0)];
+
+        while let Some((directory, depth)) = directories.pop() {
+            let entries = match fs::read_dir(&directory) {
+                Ok(entries) => entries,
+                // Nothing recorded yet
+                Err(e) if e.kind() == ErrorKind::NotFound && directory == self.root => return Ok(found),
+                Err(e) => return Err(e),
+            };
+
+            let file_level = depth + 1 == self.components.len();
+
+            for entry in entries {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                if metadata.is_dir() && !file_level && self.components[depth].matches(&name) {
+                    directories.push((entry.path(), depth + 1));
+                } else if metadata.is_file() && file_level && self.belongs(&name) && !is_open(&entry.path(), current) {
+                    found.push(Candidate {
+                        path: entry.path(),
+                        size: metadata.len(),
+                        modified: metadata.modified()?,
+                    });
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Whether a file name is one the template expands to, or belongs to one.
+    /// Recordings may have a rollover sequence number, see `OutputPaths`, and sidecar files.
+    fn belongs(&self, name: &str) -> bool {
+        let pattern = &self.components[self.components.len() - 1];
+
+        // Quarantined partial records are named after their offset as well
+        let recording = match name.rsplit_once('.') {
+            Some((stem, "partial")) => strip_number(stem),
+            Some((stem, extension)) if SIDECAR_EXTENSIONS.contains(&extension) => Some(stem),
+            _ => None,
+        };
+
+        [Some(name), recording]
+            .into_iter()
+            .flatten()
+            .any(|name| pattern.matches(name) || strip_number(name).is_some_and(|stem| pattern.matches(stem)))
+    }
+
+    /// Whether a file is an uncompressed recording rather than a sidecar
+    fn is_recording(&self, path: &Path) -> bool {
+        !path
+            .extension()
+            .is_some_and(|extension| SIDECAR_EXTENSIONS.contains(&extension.to_string_lossy().as_ref()))
+    }
+
+    /// Replace an uncompressed recording with a zstd compressed one, keeping its modification time.
+    /// Its index points into the uncompressed file, so it is rebuilt, or deleted if that fails.
+    fn compress(&self, candidate: &Candidate, current: &Mutex<String>) -> Result<(), Error> {
+        let path = candidate.path.to_string_lossy().to_string();
+
+        let header = FileHeader::read_path(&path)?;
+        if header.compression != Compression::None || header.is_legacy() {
+            return Ok(());
+        }
+
+        let compressed_path = format!("{}.{}", path, COMPRESSING_EXTENSION);
+        let _ = fs::remove_file(&compressed_path);
+
+        let compressed = convert(&path, &compressed_path, Compression::Zstd).and_then(|_| {
+            let file = OpenOptions::new().write(true).open(&compressed_path)?;
+            file.sync_all()?;
+            file.set_modified(candidate.modified)
+        });
+
+        // Rollover may have reopened the file meanwhile, keep it then
+        if compressed.is_err() || is_open(&candidate.path, current) {
+            let _ = fs::remove_file(&compressed_path);
+            return compressed;
+        }
+
+        fs::rename(&compressed_path, &path)?;
+
+        let index = index_path(&path);
+        if Path::new(&index).exists() {
+            if let Err(e) = self.rebuild_index(&path, candidate.modified) {
+                println!("Retention deleted index {} instead of rebuilding it: {}", index, e);
+                fs::remove_file(&index)?;
+            }
+        }
+
+        println!(
+            "Retention compressed {}, {} to {} bytes",
+            path,
+            candidate.size,
+            fs::metadata(&path)?.len()
+        );
+
+        Ok(())
+    }
+
+    fn rebuild_index(&self, recording: &str, modified: SystemTime) -> Result<(), Error> {
+        if self.index_records == 0 && self.index_interval_ms == 0 {
+            return Err(Error::other("indexing is disabled"));
+        }
+
+        let mut records = RecordReader::new(BufReader::new(File::open(recording)?))?;
+        Index::build(&mut records, self.index_records, self.index_interval_ms)?.save(recording)?;
+
+        OpenOptions::new().write(true).open(index_path(recording))?.set_modified(modified)
+    }
+
+    fn delete(&self, candidate: &Candidate, reason: &str, current: &Mutex<String>) -> Result<(), Error> {
+        if is_open(&candidate.path, current) {
+            return Ok(());
+        }
+
+        fs::remove_file(&candidate.path)?;
+        println!(
+            "Retention deleted {}, {} bytes, {}",
+            candidate.path.display(),
+            candidate.size,
+            reason
+        );
+
+        Ok(())
+    }
+}
+
+/// Name without its last extension if that is a number
+fn strip_number(name: &str) -> Option<&str> {
+    let (stem, number) = name.rsplit_once('.')?;
+    (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then_some(stem)
+}
+
+/// Whether a path is the file being written or its index
+fn is_open(path: &Path, current: &Mutex<String>) -> bool {
+    let current = current.lock().unwrap();
+
+    [current.clone(), index_path(&current)]
+        .iter()
+        .any(|open| same_file(path, Path::new(open)))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::format::{Record, RecordingWriter};
+
+    fn retention(file_path: &str) -> Result<Retention, Error> {
+        let block: Block =
+            serde_json::from_value(json!({ "mode": "file", "file_path": file_path, "retention_days": 1 }))?;
+        Ok(Retention::from_block(&block)?.unwrap())
+    }
+
+    #[test]
+    fn anchors_on_literal_directory_or_prefix() {
+        let anchored = retention("data/%Y-%m-%d/$source_ip_$seq.rec").unwrap();
+        assert_eq!(anchored.root, PathBuf::from("data"));
+        assert_eq!(anchored.components.len(), 2);
+
+        assert_eq!(retention("/rec_$date").unwrap().root, PathBuf::from("/"));
+        assert_eq!(retention("/var/rec/%H/x.rec").unwrap().root, PathBuf::from("/var/rec"));
+        assert_eq!(retention("./rec_%H.rec").unwrap().root, PathBuf::from("."));
+        assert_eq!(retention("out.rec").unwrap().root, PathBuf::from("."));
+
+        for template in ["$date/x.rec", "%Y.rec", "/%Y/x.rec", "./$seq"] {
+            assert_eq!(retention(template).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", template);
+        }
+    }
+
+    #[test]
+    fn matches_expanded_names_with_sequence_and_sidecars() {
+        let dated = retention("/rec_$date").unwrap();
+
+        for name in [
+            "rec_2024-09-24",
+            "rec_2024-09-24.1",
+            "rec_2024-09-24.idx",
+            "rec_2024-09-24.1.idx",
+            "rec_2024-09-24.compressing",
+            "rec_2024-09-24.12.compressing",
+            "rec_2024-09-24.1.4096.partial",
+        ] {
+            assert!(dated.belongs(name), "{}", name);
+        }
+
+        for name in ["rec_x", "bin", "rec_2024-09-24.txt", "rec_2024-09-24.partial", "xrec_2024-09-24"] {
+            assert!(!dated.belongs(name), "{}", name);
+        }
+
+        let timed = retention("data/%H%M_$hostname.rec").unwrap();
+        assert!(timed.belongs("1330_vm.rec"));
+        assert!(timed.belongs("1330_.rec.2"));
+        assert!(!timed.belongs("13x0_vm.rec"));
+        assert!(!timed.belongs("1330_vm.pcap"));
+    }
+
+    #[test]
+    fn scans_only_directories_of_the_template() {
+        let root = env::temp_dir().join(format!("recorder-retention-test-{}", process::id()));
+        let touch = |path: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"recording").unwrap();
+        };
+
+        for path in [
+            "daily/2024-09-24/rec_0000.rec",
+            "daily/2024-09-24/rec_0001.rec.idx",
+            "daily/2024-09-24/notes.txt",
+            "daily/2024-09-24/sub/rec_0000.rec",
+            "daily/other/rec_0000.rec",
+            "daily/rec_0000.rec",
+            "flat/rec_2024-09-24",
+            "flat/rec_2024-09-24.1",
+            "flat/nested/rec_2024-09-24",
+            "flat/keep.rec",
+        ] {
+            touch(path);
+        }
+
+        let found = |template: &str| {
+            let retention = retention(&format!("{}/{}", root.display(), template)).unwrap();
+            let current = Mutex::new(root.join("daily/2024-09-24/rec_0001.rec").to_string_lossy().to_string());
+
+            let mut found: Vec<_> = retention
+                .candidates(&current)
+                .unwrap()
+                .into_iter()
+                .map(|candidate| candidate.path.strip_prefix(&root).unwrap().to_string_lossy().to_string())
+                .collect();
+            found.sort();
+            found
+        };
+
+        // The open file's index is left alone
+        assert_eq!(found("daily/%Y-%m-%d/rec_$seq.rec"), ["daily/2024-09-24/rec_0000.rec"]);
+        assert_eq!(found("flat/rec_$date"), ["flat/rec_2024-09-24", "flat/rec_2024-09-24.1"]);
+        assert!(found("missing/rec_$date").is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Uncompressed recording of 1000 records, last modified `days` ago
+    fn write_recording(path: &Path, days: u32) {
+        let mut header = FileHeader::new(vec![]);
+        let start = header.created_at_nanos().unwrap();
+
+        let mut writer = RecordingWriter::create(File::create(path).unwrap(), &mut header).unwrap();
+        for i in 0..1000u64 {
+            let record = Record {
+                timestamp: start + i * 1_000_000,
+                monotonic: i * 1_000_000,
+                data: vec![i as u8; 100],
+                ..Default::default()
+            };
+            writer.write_record(&record).unwrap();
+        }
+
+        writer.finish().unwrap().set_modified(SystemTime::now() - DAY * days).unwrap();
+    }
+
+    fn with_settings(file_path: &str, settings: serde_json::Value) -> Retention {
+        let mut block = json!({ "mode": "file", "file_path": file_path, "no_headers": true, "index_records": 100 });
+        block.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+
+        Retention::from_block(&serde_json::from_value(block).unwrap()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn compresses_into_readable_recordings_with_rebuilt_index() {
+        let root = env::temp_dir().join(format!("recorder-retention-compress-test-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let recording = root.join("rec_0000.rec");
+        let path = recording.to_str().unwrap();
+
+        write_recording(&recording, 3);
+        let mut records = RecordReader::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        Index::build(&mut records, 100, 0).unwrap().save(path).unwrap();
+        let size = fs::metadata(path).unwrap().len();
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+
+        let retention = with_settings(&format!("{}/rec_$seq.rec", root.display()), json!({ "compress_after_days": 2 }));
+        retention.run(&Mutex::new(String::new())).unwrap();
+
+        assert!(fs::metadata(path).unwrap().len() < size);
+        assert_eq!(fs::metadata(path).unwrap().modified().unwrap(), modified);
+
+        let mut records = RecordReader::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        assert_eq!(records.header.compression, Compression::Zstd);
+        let mut count = 0;
+        while let Some(record) = records.next_record().unwrap() {
+            assert_eq!(record.data, [count as u8; 100]);
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+
+        // The index now points into the compressed blocks
+        let index = Index::load(path).unwrap();
+        let entry = index.find(Some(500), None).unwrap();
+        records.seek_to(entry).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap().data[0], entry.record as u8);
+
+        // Compressed recordings are left alone
+        retention.run(&Mutex::new(String::new())).unwrap();
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_enforcing_size_past_failing_files() {
+        let root = env::temp_dir().join(format!("recorder-retention-size-test-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        // Header cut off, so compressing it fails
+        write_recording(&root.join("rec_0000.rec"), 5);
+        let file = File::options().write(true).open(root.join("rec_0000.rec")).unwrap();
+        file.set_len(12).unwrap();
+        file.set_modified(SystemTime::now() - DAY * 5).unwrap();
+        drop(file);
+        write_recording(&root.join("rec_0001.rec"), 4);
+        write_recording(&root.join("rec_0002.rec"), 3);
+        write_recording(&root.join("rec_0003.rec"), 0);
+
+        let limit = fs::metadata(root.join("rec_0003.rec")).unwrap().len() as f64 / (1024.0 * 1024.0 * 1024.0);
+        let retention = with_settings(
+            &format!("{}/rec_$seq.rec", root.display()),
+            json!({ "compress_after_days": 1, "retention_max_gb": limit }),
+        );
+        retention.run(&Mutex::new(String::new())).unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        left.sort();
+        assert_eq!(left, ["rec_0003.rec"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
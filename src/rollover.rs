@@ -0,0 +1,204 @@
+//! When the file output closes its recording and starts a new one.
+//! Rollover happens at a time of day, after the file reaches a size, or after it has been
+//! open for a duration, whichever comes first.
+
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Days, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+use crate::recorder::Block;
+
+/// Timezone the rollover time of day and file path times are taken in.
+/// Written as `local`, `UTC` or a fixed offset like `+02:00`.
+#[derive(Debug, Clone, Copy)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Zone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Zone, Error> {
+        match s {
+            "" | "local" => Ok(Zone::Local),
+            "UTC" | "utc" | "Z" => Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            _ => s
+                .parse()
+                .map(Zone::Fixed)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid rollover timezone {:?}", s))),
+        }
+    }
+}
+
+impl Zone {
+    /// Wall clock time in this timezone at a point in time
+    pub fn local_time(&self, at: SystemTime) -> NaiveDateTime {
+        let utc = DateTime::<Utc>::from(at);
+
+        match self {
+            Zone::Local => utc.with_timezone(&Local).naive_local(),
+            Zone::Fixed(offset) => utc.with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// Point in time of a wall clock time in this timezone.
+    /// None if the time is skipped by a daylight saving change.
+    fn instant(&self, time: NaiveDateTime) -> Option<SystemTime> {
+        let nanos = match self {
+            Zone::Local => Local.from_local_datetime(&time).earliest()?.timestamp_nanos_opt()?,
+            Zone::Fixed(offset) => offset.from_local_datetime(&time).earliest()?.timestamp_nanos_opt()?,
+        };
+
+        Some(UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64))
+    }
+
+    /// First occurrence of a time of day after a point in time
+    fn next(&self, time: NaiveTime, after: SystemTime) -> SystemTime {
+        let mut date = self.local_time(after).date();
+
+        loop {
+            if let Some(at) = self.instant(date.and_time(time)).filter(|at| *at > after) {
+                return at;
+            }
+
+            date = date + Days::new(1);
+        }
+    }
+}
+
+/// Rollover settings of a file output
+#[derive(Debug, Clone)]
+pub struct Rollover {
+    time: Option<NaiveTime>,
+    pub zone: Zone,
+    max_bytes: u64,
+    max_duration: Option<Duration>,
+    /// Next rollover by time of day
+    next_at: Option<SystemTime>,
+}
+
+impl Rollover {
+    pub fn from_block(block: &Block) -> Result<Rollover, Error> {
+        let time = match block.rollover_time.as_str() {
+            "" => None,
+            time => Some(
+                NaiveTime::parse_from_str(time, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid rollover time {:?}", time)))?,
+            ),
+        };
+
+        Ok(Rollover {
+            time,
+            zone: block.rollover_timezone.parse()?,
+            max_bytes: block.rollover_size_mb * 1024 * 1024,
+            max_duration: (block.rollover_duration_s > 0).then(|| Duration::from_secs(block.rollover_duration_s)),
+            next_at: None,
+        })
+    }
+
+    /// Start timing a file opened at `now`
+    pub fn opened(&mut self, now: SystemTime) {
+        self.next_at = self.time.map(|time| self.zone.next(time, now));
+    }
+
+    /// Whether a file of `size` bytes opened at `opened` must be closed before writing
+    /// something captured at `now`
+    pub fn due(&self, size: u64, opened: SystemTime, now: SystemTime) -> bool {
+        self.next_at.is_some_and(|at| now >= at)
+            || (self.max_bytes > 0 && size >= self.max_bytes)
+            || self
+                .max_duration
+                .is_some_and(|duration| now.duration_since(opened).unwrap_or_default() >= duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    use super::*;
+
+    fn at(zone: Zone, date: (i32, u32, u32), time: (u32, u32, u32)) -> SystemTime {
+        let date = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap();
+        zone.instant(date.and_hms_opt(time.0, time.1, time.2).unwrap()).unwrap()
+    }
+
+    fn rollover(settings: serde_json::Value) -> Result<Rollover, Error> {
+        let mut block = json!({ "mode": "file", "file_path": "rec" });
+        block.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+
+        Rollover::from_block(&serde_json::from_value(block).unwrap())
+    }
+
+    #[test]
+    fn parses_zones() {
+        let offset = |zone: &str| match zone.parse::<Zone>().unwrap() {
+            Zone::Fixed(offset) => Some(offset.local_minus_utc()),
+            Zone::Local => None,
+        };
+
+        assert_eq!(offset("local"), None);
+        assert_eq!(offset(""), None);
+        assert_eq!(offset("UTC"), Some(0));
+        assert_eq!(offset("+02:00"), Some(7200));
+        assert_eq!(offset("-05:30"), Some(-19800));
+        assert_eq!("Mars/Olympus".parse::<Zone>().unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn next_time_of_day_is_today_or_tomorrow() {
+        let zone: Zone = "+02:00".parse().unwrap();
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+        let noon = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+
+        let morning = at(zone, (2024, 3, 10), (9, 30, 0));
+        assert_eq!(zone.next(noon, morning), at(zone, (2024, 3, 10), (12, 0, 0)));
+        assert_eq!(zone.next(midnight, morning), at(zone, (2024, 3, 11), (0, 0, 0)));
+
+        // Right at the time of day the next rollover is a day later
+        let exactly = at(zone, (2024, 3, 10), (12, 0, 0));
+        assert_eq!(zone.next(noon, exactly), at(zone, (2024, 3, 11), (12, 0, 0)));
+
+        // Taken in the zone, not in UTC, 23:00 UTC is already the next day at +02:00
+        let utc: Zone = "UTC".parse().unwrap();
+        let late = at(utc, (2024, 12, 31), (23, 0, 0));
+        assert_eq!(zone.next(midnight, late), at(utc, (2025, 1, 1), (22, 0, 0)));
+    }
+
+    #[test]
+    fn due_by_time_size_or_duration() {
+        let zone: Zone = "UTC".parse().unwrap();
+        let opened = at(zone, (2024, 3, 10), (23, 0, 0));
+        let minutes = |m: u64| opened + Duration::from_secs(m * 60);
+
+        let mut by_time = rollover(json!({ "rollover_time": "23:30", "rollover_timezone": "UTC" })).unwrap();
+        by_time.opened(opened);
+        assert!(!by_time.due(0, opened, minutes(29)));
+        assert!(by_time.due(0, opened, minutes(30)));
+
+        let by_size = rollover(json!({ "rollover_size_mb": 1 })).unwrap();
+        assert!(!by_size.due(1024 * 1024 - 1, opened, opened));
+        assert!(by_size.due(1024 * 1024, opened, opened));
+
+        let by_duration = rollover(json!({ "rollover_duration_s": 600 })).unwrap();
+        assert!(!by_duration.due(0, opened, minutes(9)));
+        assert!(by_duration.due(0, opened, minutes(10)));
+
+        let never = rollover(json!({})).unwrap();
+        assert!(!never.due(u64::MAX, opened, minutes(100_000)));
+    }
+
+    #[test]
+    fn rejects_invalid_rollover_times() {
+        assert!(rollover(json!({ "rollover_time": "00:00:30" })).is_ok());
+        assert_eq!(rollover(json!({ "rollover_time": "25:00" })).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(rollover(json!({ "rollover_time": "noon" })).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,166 @@
+//! Health of a file output, logged on every change and periodically while degraded,
+//! and optionally written as JSON to a status file for monitoring
+
+use std::{
+    fs,
+    io::Error,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use serde::Serialize;
+
+/// How often a degraded output repeats its status
+const ALERT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteState {
+    Ok,
+    /// Waiting to retry a failed write, inputs block meanwhile
+    Retrying,
+    /// Writing to the failover directory
+    FailedOver,
+    /// Dropping records which cannot be written
+    Dropping,
+    /// Gave up, the recorder stops
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WriteStatus {
+    pub state: WriteState,
+    /// File being written
+    pub path: String,
+    pub written_records: u64,
+    pub write_errors: u64,
+    pub dropped_records: u64,
+    pub dropped_bytes: u64,
+    pub last_error: String,
+    pub updated_at: String,
+}
+
+/// Tracks and reports the status of one output
+#[derive(Debug)]
+pub struct StatusReporter {
+    pub status: WriteStatus,
+    /// Where the status is written as JSON, empty disables it
+    status_file: String,
+    last_report: Instant,
+    since: Instant,
+}
+
+impl StatusReporter {
+    pub fn new(status_file: &str) -> StatusReporter {
+        StatusReporter {
+            status: WriteStatus {
+                state: WriteState::Ok,
+                path: String::new(),
+                written_records: 0,
+                write_errors: 0,
+                dropped_records: 0,
+                dropped_bytes: 0,
+                last_error: String::new(),
+                updated_at: String::new(),
+            },
+            status_file: status_file.to_string(),
+            last_report: Instant::now(),
+            since: Instant::now(),
+        }
+    }
+
+    /// Count a failed write or open
+    pub fn error(&mut self, error: &Error) {
+        self.status.write_errors += 1;
+        self.status.last_error = error.to_string();
+    }
+
+    pub fn written(&mut self) {
+        self.status.written_records += 1;
+
+        if matches!(self.status.state, WriteState::Retrying | WriteState::Dropping) {
+            self.set_state(WriteState::Ok);
+        }
+    }
+
+    pub fn dropped(&mut self, bytes: usize) {
+        self.status.dropped_records += 1;
+        self.status.dropped_bytes += bytes as u64;
+    }
+
+    pub fn set_path(&mut self, path: &str) {
+        self.status.path = path.to_string();
+        self.save();
+    }
+
+    /// Change state, logging the change
+    pub fn set_state(&mut self, state: WriteState) {
+        if state == self.status.state {
+            return;
+        }
+
+        match state {
+            WriteState::Ok => println!(
+                "Writing to {} recovered after {:?}, {} records dropped",
+                self.status.path,
+                self.since.elapsed(),
+                self.status.dropped_records
+            ),
+            WriteState::Retrying => println!(
+                "Writing to {} failed: {}, retrying until it succeeds",
+                self.status.path, self.status.last_error
+            ),
+            WriteState::FailedOver => println!(
+                "Writing to {} failed: {}, failing over",
+                self.status.path, self.status.last_error
+            ),
+            WriteState::Dropping => println!(
+                "Writing to {} failed: {}, dropping records until it succeeds",
+                self.status.path, self.status.last_error
+            ),
+            WriteState::Failed => println!("Writing to {} failed: {}", self.status.path, self.status.last_error),
+        }
+
+        self.status.state = state;
+        self.since = Instant::now();
+        self.report();
+    }
+
+    /// Repeat the status every interval while degraded
+    pub fn tick(&mut self) {
+        if self.last_report.elapsed() < ALERT_INTERVAL {
+            return;
+        }
+
+        if !matches!(self.status.state, WriteState::Ok | WriteState::FailedOver) {
+            println!(
+                "Output {} {:?} for {:?}: {} errors, {} records dropped, last error: {}",
+                self.status.path,
+                self.status.state,
+                self.since.elapsed(),
+                self.status.write_errors,
+                self.status.dropped_records,
+                self.status.last_error
+            );
+        }
+
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.last_report = Instant::now();
+        self.save();
+    }
+
+    fn save(&mut self) {
+        if self.status_file.is_empty() {
+            return;
+        }
+
+        self.status.updated_at = Local::now().to_rfc3339();
+
+        // A full disk may well fail this too, the log has the same information
+        let json = serde_json::to_string_pretty(&self.status).unwrap();
+        let _ = fs::write(&self.status_file, json);
+    }
+}
@@ -0,0 +1,128 @@
+//! Expansion of `Block.file_path` templates.
+//!
+//! strftime tokens like `%Y`, `%m`, `%d`, `%H`, `%M` expand to the time the file is opened,
+//! in the rollover timezone. Placeholders:
+//! - `$date`: date as `%Y-%m-%d`
+//! - `$hostname`: name of this machine
+//! - `$mode`, `$source_ip`, `$source_port`: settings of the block producing the data,
+//!   the first input for outputs
+//! - `$pid`: id of the recorder process
+//! - `$seq`: rollover sequence number, 4 digits, counting up while the rest of the path
+//!   stays the same and starting over at 0 once it changes
+//!
+//! For example `data/%Y-%m-%d/$source_ip_$source_port_$seq.rec` expands to
+//! `data/2024-09-24/233.1.78.11_33566_0000.rec` for the first file of a day,
+//! and to `data/2024-09-24/233.1.78.11_33566_0001.rec` after its first rollover.
+
+use std::{
+    io::{Error, ErrorKind},
+    process,
+    time::SystemTime,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    NaiveDateTime,
+};
+
+use crate::{recorder::Block, rollover::Rollover, utils::hostname};
+
+/// Expand a path template for a file opened at local `time`, see the module docs
+pub fn expand(template: &str, source: &Block, time: NaiveDateTime, sequence: u32) -> Result<String, Error> {
+    let mode = serde_json::to_value(&source.mode)
+        .ok()
+        .and_then(|mode| mode.as_str().map(str::to_string))
+        .unwrap_or_default();
+
+    let placeholders = [
+        ("date", "%Y-%m-%d".to_string()),
+        ("hostname", hostname()),
+        ("mode", mode),
+        ("source_ip", source.source_ip.clone()),
+        ("source_port", source.source_port.to_string()),
+        ("pid", process::id().to_string()),
+        ("seq", format!("{:04}", sequence)),
+    ];
+
+    let mut path = template.to_string();
+    for (name, value) in placeholders {
+        // Values are not strftime patterns, except for $date which is one on purpose
+        let value = if name == "date" { value } else { value.replace('%', "%%") };
+
+        path = path.replace(&format!("${}", name), &value);
+    }
+
+    let items: Vec<Item> = StrftimeItems::new(&path).collect();
+    if items.contains(&Item::Error) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid strftime token in file path {:?}", template),
+        ));
+    }
+
+    Ok(time.format_with_items(items.into_iter()).to_string())
+}
+
+/// Expand the path of a file an input replays, as of now in the block's rollover timezone
+pub fn input_path(template: &str, block: &Block) -> Result<String, Error> {
+    let zone = Rollover::from_block(block)?.zone;
+
+    expand(template, block, zone.local_time(SystemTime::now()), 0)
+}
+
+/// Whether a template gives every rollover sequence number its own path
+pub fn has_sequence(template: &str) -> bool {
+    template.contains("$seq")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    use super::*;
+
+    fn block() -> Block {
+        serde_json::from_value(json!({ "mode": "udp", "source_ip": "233.1.78.11", "source_port": 33566 })).unwrap()
+    }
+
+    fn time() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 9, 24).unwrap().and_hms_opt(23, 5, 9).unwrap()
+    }
+
+    #[test]
+    fn expands_documented_example() {
+        let template = "data/%Y-%m-%d/$source_ip_$source_port_$seq.rec";
+
+        assert_eq!(expand(template, &block(), time(), 0).unwrap(), "data/2024-09-24/233.1.78.11_33566_0000.rec");
+        assert_eq!(expand(template, &block(), time(), 1).unwrap(), "data/2024-09-24/233.1.78.11_33566_0001.rec");
+    }
+
+    #[test]
+    fn expands_placeholders_and_strftime_tokens() {
+        let path = expand("$date/%H%M%S_$mode_$pid", &block(), time(), 0).unwrap();
+
+        assert_eq!(path, format!("2024-09-24/230509_udp_{}", process::id()));
+    }
+
+    #[test]
+    fn placeholder_values_are_not_strftime_tokens() {
+        let mut block = block();
+        block.source_ip = "%Y".to_string();
+
+        assert_eq!(expand("$source_ip.rec", &block, time(), 0).unwrap(), "%Y.rec");
+    }
+
+    #[test]
+    fn rejects_invalid_strftime_tokens() {
+        let error = expand("data/%Q.rec", &block(), time(), 0).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn detects_sequence_placeholder() {
+        assert!(has_sequence("rec_$seq.rec"));
+        assert!(!has_sequence("rec_$date.rec"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Throughput of recording through the bus: packets are sent with the bus writer inputs use
+//! and written by file outputs, the way the udp input and the file adapter record.
+//! Build in release mode, debug builds print every packet.
+
+use std::{
+    fs,
+    io::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+
+use bus::Bus;
+use serde_json::json;
+
+use crate::{
+    adapters::file_adapter::FileAdapter,
+    constants::{BUF_SIZE, BUS_SIZE},
+    message::Message,
+    recorder::{Block, BusWriter, Output},
+};
+
+use super::usage;
+
+const USAGE: &str =
+    "bench-bus <directory> [packet size, default 100] [packet count, default 1000000] [outputs, default 2]";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (directory, size, count, outputs) = match args {
+        [directory] => (directory, 100, 1_000_000, 2),
+        [directory, size] => (directory, parse(size)?, 1_000_000, 2),
+        [directory, size, count] => (directory, parse(size)?, parse(count)?, 2),
+        [directory, size, count, outputs] => (directory, parse(size)?, parse(count)?, parse(outputs)?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    if size > BUF_SIZE {
+        return Err(Error::other(format!("packet size must be at most {} bytes", BUF_SIZE)));
+    }
+
+    fs::create_dir_all(directory)?;
+    println!("{} packets of {} bytes, {} outputs", count, size, outputs);
+
+    let mut bus = Bus::<Message>::new(BUS_SIZE);
+    let mut paths = vec![];
+    let mut writers = vec![];
+
+    for i in 0..outputs {
+        let path = Path::new(directory).join(format!("bench-bus-{}.rec", i));
+        let _ = fs::remove_file(&path);
+
+        let block: Block = serde_json::from_value(json!({
+            "mode": "file",
+            "file_path": path.to_string_lossy(),
+            "no_headers": true,
+            "index_records": 0,
+            "index_interval_ms": 0,
+        }))?;
+
+        let mut channel = bus.add_rx();
+        writers.push(thread::spawn(move || FileAdapter {}.write(block, &[], &mut channel)));
+        paths.push(path);
+    }
+
+    let bus = Arc::new(Mutex::new(Some(bus)));
+    let channel = BusWriter::new(bus.clone(), 0);
+
+    // Same as a udp input, one receive buffer reused for every packet
+    let buf = [7u8; BUF_SIZE];
+    let start = Instant::now();
+
+    for _ in 0..count {
+        channel.send(&buf[..size], None);
+    }
+
+    // Outputs write what is left on the bus and return once it is closed
+    bus.lock().unwrap().take();
+    for writer in writers {
+        writer.join().map_err(|_| Error::other("output panicked"))??;
+    }
+
+    let elapsed = start.elapsed();
+
+    for path in paths {
+        fs::remove_file(path)?;
+    }
+
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "{:>10.0} packets/s {:>8.1} MB/s ({:?})",
+        count as f64 / secs,
+        (count * size) as f64 / secs / 1_000_000.0,
+        elapsed
+    );
+
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T, Error> {
+    arg.parse().map_err(|_| usage(USAGE))
+}
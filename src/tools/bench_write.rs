@@ -0,0 +1,97 @@
+//! Throughput of the file output with different buffering and fsync settings,
+//! from writing every record right away and syncing it to fully buffered without syncing.
+//! Build in release mode, debug builds print every record.
+
+use std::{
+    fs,
+    io::Error,
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use serde_json::json;
+
+use crate::{adapters::file_adapter::FileWriter, message::Message, recorder::Block};
+
+use super::usage;
+
+const USAGE: &str = "bench-write <directory> [packet size, default 100] [packet count, default 20000]";
+
+/// Name and buffering settings of every run
+const PRESETS: [(&str, u64, usize, &str, u64); 5] = [
+    ("unbuffered, fsync every record", 0, 0, "interval", 0),
+    ("unbuffered", 0, 0, "never", 0),
+    ("64 KiB, fsync every 100 ms", 100, 65536, "interval", 100),
+    ("64 KiB", 100, 65536, "never", 0),
+    ("1 MiB", 1000, 1 << 20, "never", 0),
+];
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (directory, size, count) = match args {
+        [directory] => (directory, 100, 20000),
+        [directory, size] => (directory, parse(size)?, 20000),
+        [directory, size, count] => (directory, parse(size)?, parse(count)?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    fs::create_dir_all(directory)?;
+    println!("{} packets of {} bytes", count, size);
+
+    let payload: Arc<[u8]> = vec![7; size].into();
+
+    for (i, (name, flush_interval_ms, max_buffered_bytes, fsync, fsync_interval_ms)) in PRESETS.into_iter().enumerate() {
+        let path = Path::new(directory).join(format!("bench-write-{}.rec", i));
+        let _ = fs::remove_file(&path);
+
+        let block: Block = serde_json::from_value(json!({
+            "mode": "file",
+            "file_path": path.to_string_lossy(),
+            "no_headers": true,
+            "index_records": 0,
+            "index_interval_ms": 0,
+            "flush_interval_ms": flush_interval_ms,
+            "max_buffered_bytes": max_buffered_bytes,
+            "fsync": fsync,
+            "fsync_interval_ms": fsync_interval_ms,
+        }))?;
+
+        let mut writer = FileWriter::new(&block, &[])?;
+        writer.write(None)?;
+
+        let start = Instant::now();
+
+        for seq in 0..count {
+            let message = Message {
+                wall_time: SystemTime::now(),
+                mono_time: Instant::now(),
+                input_id: 0,
+                source: None,
+                destination: None,
+                seq,
+                data: payload.clone(),
+            };
+
+            writer.write(Some(&message))?;
+        }
+
+        writer.close();
+        let elapsed = start.elapsed();
+        fs::remove_file(&path)?;
+
+        let secs = elapsed.as_secs_f64();
+        println!(
+            "{:<32} {:>10.0} packets/s {:>8.1} MB/s ({:?})",
+            name,
+            count as f64 / secs,
+            (count as usize * size) as f64 / secs / 1_000_000.0,
+            elapsed
+        );
+    }
+
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T, Error> {
+    arg.parse().map_err(|_| usage(USAGE))
+}
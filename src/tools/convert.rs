@@ -0,0 +1,99 @@
+//! Convert recordings in older formats, including legacy files without a file header,
+//! to the current format, and compress or decompress recordings
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error, ErrorKind},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::format::{Compression, FileHeader, RecordReader, RecordingWriter, FORMAT_VERSION};
+
+use super::{report_corruption, usage};
+
+const USAGE: &str = "convert <recording> <output file> [compression: none, zstd or lz4, default none]";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (input, output, compression) = match args {
+        [input, output] => (input, output, Compression::None),
+        [input, output, compression] => (input, output, compression.parse()?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    let header = FileHeader::read_path(input)?;
+    if header.version == FORMAT_VERSION && header.compression == compression {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} already has format version {} with compression {:?}",
+                input, FORMAT_VERSION, compression
+            ),
+        ));
+    }
+
+    let (records, count) = convert(input, output, compression)?;
+
+    report_corruption(&records, input);
+
+    let remaining = fs::metadata(input)?.len() - records.offset();
+    if remaining > 0 {
+        println!("Ignored {} bytes of partial record at the end of {}", remaining, input);
+    }
+
+    println!(
+        "Converted {} records from {} format version {} to {}, compression {:?}",
+        count, input, records.header.version, output, compression
+    );
+
+    Ok(())
+}
+
+/// Write `input` to a new recording `output` in the current format with `compression`.
+/// Returns the reader of `input`, for its header and corruption, and the number of records written.
+pub fn convert(
+    input: &str,
+    output: &str,
+    compression: Compression,
+) -> Result<(RecordReader<BufReader<File>>, u64), Error> {
+    let mut records = RecordReader::new(BufReader::new(File::open(input)?))?;
+
+    let mut header = FileHeader::new(records.header.inputs.clone());
+    header.created_at = records.header.created_at.clone();
+    header.checksums = true;
+    header.compression = compression;
+
+    // Legacy files only store time diffs and only tell us when they were last written,
+    // which is the end of the recording. First pass finds the duration to get the start.
+    let mut legacy_start = 0;
+    if records.header.is_legacy() {
+        let mut duration = 0;
+        while let Some(record) = records.next_record()? {
+            duration = record.monotonic;
+        }
+        records.rewind()?;
+
+        let modified = fs::metadata(input)?.modified()?;
+        let end = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+
+        legacy_start = end.saturating_sub(duration);
+        header.created_at = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(legacy_start)).to_rfc3339();
+    }
+
+    let mut writer = RecordingWriter::create(BufWriter::new(File::create_new(output)?), &mut header)?;
+
+    let mut count = 0;
+    while let Some(mut record) = records.next_record()? {
+        if records.header.is_legacy() {
+            record.timestamp = legacy_start + record.monotonic;
+        }
+
+        writer.write_record(&record)?;
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    Ok((records, count))
+}
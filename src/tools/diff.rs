@@ -0,0 +1,540 @@
+//! Compare two recordings of the same streams, for example from recorders on redundant boxes.
+//!
+//! Records are aligned by their source and either their whole payload or a sequence number field in it.
+//! Both recordings are read in capture time order and a record without a partner within the window
+//! counts as missing on the other side. With sequence numbers, partners whose payloads differ are mismatches.
+//! Divergence makes the command fail, so it can run as a scheduled check.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    net::SocketAddr,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::format::{Record, RecordReader};
+
+use super::{open_timed, report_corruption, usage};
+
+const USAGE: &str = "diff <recording> <recording> [--seq <offset>:<1|2|4|8>[le]] [--window <seconds>]
+--seq aligns by an unsigned big or little endian sequence number at a payload offset instead of the whole payload
+--window is how far apart in capture time partners may be, 10 seconds by default";
+const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+/// Differences of each kind printed in detail
+const MAX_EXAMPLES: usize = 10;
+/// Upper bounds of the skew histogram buckets, for the skew either way
+const SKEW_BUCKETS: [Duration; 7] = [
+    Duration::from_micros(1),
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// Sequence number field of payloads
+#[derive(Debug, Clone, Copy)]
+struct SequenceField {
+    offset: usize,
+    size: usize,
+    little_endian: bool,
+}
+
+impl FromStr for SequenceField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SequenceField, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid sequence field {:?}", s));
+
+        let (offset, size) = s.split_once(':').ok_or_else(invalid)?;
+        let (size, little_endian) = match size.strip_suffix("le") {
+            Some(size) => (size, true),
+            None => (size, false),
+        };
+
+        let field = SequenceField {
+            offset: offset.parse().map_err(|_| invalid())?,
+            size: size.parse().map_err(|_| invalid())?,
+            little_endian,
+        };
+
+        match field.size {
+            1 | 2 | 4 | 8 => Ok(field),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl SequenceField {
+    /// Sequence number of a payload, None if it is too short to hold one
+    fn read(&self, data: &[u8]) -> Option<u64> {
+        let bytes = data.get(self.offset..self.offset + self.size)?;
+        let mut value = [0; 8];
+
+        if self.little_endian {
+            value[..self.size].copy_from_slice(bytes);
+            Some(u64::from_le_bytes(value))
+        } else {
+            value[8 - self.size..].copy_from_slice(bytes);
+            Some(u64::from_be_bytes(value))
+        }
+    }
+}
+
+/// What records are aligned by, records of different sources never align
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    source: Source,
+    value: KeyValue,
+}
+
+/// Address of the input a record came from, its number if it has none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Address(SocketAddr),
+    Input(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum KeyValue {
+    Payload(Vec<u8>),
+    Sequence(u64),
+    /// Payload too short for the sequence field, aligned by payload
+    Short(Vec<u8>),
+}
+
+/// Record waiting for its partner from the other recording
+#[derive(Debug)]
+struct Pending {
+    number: u64,
+    timestamp: u64,
+    data: Vec<u8>,
+}
+
+/// One of the compared recordings
+struct Side {
+    path: String,
+    records: RecordReader<BufReader<File>>,
+    next: Option<Record>,
+    /// Number of `next`
+    number: u64,
+    count: u64,
+    /// Unmatched records, oldest first for every key
+    pending: HashMap<Key, VecDeque<Pending>>,
+    /// Keys and numbers of records as they were added to `pending`, matched ones are skipped on expiry
+    order: VecDeque<(Key, u64, u64)>,
+}
+
+impl Side {
+    fn open(path: &str) -> Result<Side, Error> {
+        let mut records = open_timed(path)?;
+        let next = records.next_record()?;
+
+        Ok(Side {
+            path: path.to_string(),
+            records,
+            next,
+            number: 0,
+            count: 0,
+            pending: HashMap::new(),
+            order: VecDeque::new(),
+        })
+    }
+
+    fn advance(&mut self) -> Result<Option<(u64, Record)>, Error> {
+        let Some(record) = self.next.take() else {
+            return Ok(None);
+        };
+
+        let number = self.number;
+        self.number = self.records.position();
+        self.next = self.records.next_record()?;
+        self.count += 1;
+
+        Ok(Some((number, record)))
+    }
+
+    fn key(&self, record: &Record, sequence: Option<SequenceField>) -> Key {
+        let source = match self.records.header.input_address(record.input) {
+            Some(address) => Source::Address(address),
+            None => Source::Input(record.input),
+        };
+
+        let value = match sequence {
+            Some(field) => match field.read(&record.data) {
+                Some(sequence) => KeyValue::Sequence(sequence),
+                None => KeyValue::Short(record.data.clone()),
+            },
+            None => KeyValue::Payload(record.data.clone()),
+        };
+
+        Key { source, value }
+    }
+
+    fn add(&mut self, key: Key, pending: Pending) {
+        self.order.push_back((key.clone(), pending.number, pending.timestamp));
+        self.pending.entry(key).or_default().push_back(pending);
+    }
+
+    /// Oldest unmatched record with a key
+    fn take(&mut self, key: &Key) -> Option<Pending> {
+        let queue = self.pending.get_mut(key)?;
+        let pending = queue.pop_front();
+
+        if queue.is_empty() {
+            self.pending.remove(key);
+        }
+
+        pending
+    }
+
+    /// Remove unmatched records captured before `before`, they have no partner
+    fn expire(&mut self, before: u64) -> Vec<(Key, Pending)> {
+        let mut expired = vec![];
+
+        while let Some((_, _, timestamp)) = self.order.front() {
+            if *timestamp >= before {
+                break;
+            }
+
+            let (key, number, _) = self.order.pop_front().unwrap();
+            let unmatched = self.pending.get(&key).and_then(|queue| queue.front()).is_some_and(|pending| pending.number == number);
+
+            if unmatched {
+                expired.push((key.clone(), self.take(&key).unwrap()));
+            }
+        }
+
+        expired
+    }
+}
+
+/// Capture time of the second recording minus the first for aligned records
+#[derive(Debug, Default)]
+struct Skew {
+    count: u64,
+    sum: i128,
+    min: i64,
+    max: i64,
+    /// Records per bucket of absolute skew, the last bucket holds everything above the largest bound
+    buckets: [u64; SKEW_BUCKETS.len() + 1],
+}
+
+impl Skew {
+    fn add(&mut self, skew: i64) {
+        if self.count == 0 {
+            (self.min, self.max) = (skew, skew);
+        }
+
+        self.count += 1;
+        self.sum += skew as i128;
+        self.min = self.min.min(skew);
+        self.max = self.max.max(skew);
+
+        let magnitude = Duration::from_nanos(skew.unsigned_abs());
+        let bucket = SKEW_BUCKETS
+            .iter()
+            .position(|bound| magnitude < *bound)
+            .unwrap_or(SKEW_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Differences {
+    matched: u64,
+    mismatched: u64,
+    /// Records of each recording without a partner in the other
+    missing: [u64; 2],
+    skew: Skew,
+    examples: Vec<String>,
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [first, second, options @ ..] = args else {
+        return Err(usage(USAGE));
+    };
+
+    let mut sequence = None;
+    let mut window = DEFAULT_WINDOW;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or_else(|| usage(USAGE))?;
+
+        match option.as_str() {
+            "--seq" => sequence = Some(value.parse()?),
+            "--window" => {
+                window = value
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| usage(USAGE))?
+            }
+            _ => return Err(usage(USAGE)),
+        }
+    }
+
+    let mut sides = [Side::open(first)?, Side::open(second)?];
+    let differences = compare(&mut sides, sequence, window.as_nanos() as u64)?;
+
+    for side in &sides {
+        report_corruption(&side.records, &side.path);
+    }
+
+    print_report(&differences, &sides);
+
+    if differences.mismatched > 0 || differences.missing.iter().any(|missing| *missing > 0) {
+        return Err(Error::other(format!("{} and {} differ", first, second)));
+    }
+
+    println!("{} and {} match", first, second);
+
+    Ok(())
+}
+
+/// Align the records of both recordings, partners may be up to `window` ns apart in capture time
+fn compare(sides: &mut [Side; 2], sequence: Option<SequenceField>, window: u64) -> Result<Differences, Error> {
+    let mut differences = Differences::default();
+
+    loop {
+        // Read whichever recording is behind in capture time
+        let current = match (&sides[0].next, &sides[1].next) {
+            (None, None) => break,
+            (Some(_), None) => 0,
+            (None, Some(_)) => 1,
+            (Some(a), Some(b)) => (b.timestamp < a.timestamp) as usize,
+        };
+
+        let (number, record) = sides[current].advance()?.unwrap();
+        let key = sides[current].key(&record, sequence);
+
+        // Expire first, a partner too long ago must not be aligned with this record
+        let before = record.timestamp.saturating_sub(window);
+        for side in 0..2 {
+            let expired = sides[side].expire(before);
+            report_missing(&mut differences, sides, side, expired);
+        }
+
+        match sides[1 - current].take(&key) {
+            Some(partner) => {
+                differences.matched += 1;
+
+                let (first, second) = match current {
+                    0 => ((number, record.timestamp), (partner.number, partner.timestamp)),
+                    _ => ((partner.number, partner.timestamp), (number, record.timestamp)),
+                };
+                differences.skew.add(second.1 as i64 - first.1 as i64);
+
+                if partner.data != record.data {
+                    differences.mismatched += 1;
+                    if differences.mismatched as usize <= MAX_EXAMPLES {
+                        differences.examples.push(format!(
+                            "payload differs: {} #{} and {} #{}, {}",
+                            sides[0].path,
+                            first.0,
+                            sides[1].path,
+                            second.0,
+                            describe(&key, record.timestamp, record.data.len())
+                        ));
+                    }
+                }
+            }
+            None => sides[current].add(
+                key,
+                Pending {
+                    number,
+                    timestamp: record.timestamp,
+                    data: record.data,
+                },
+            ),
+        }
+    }
+
+    for side in 0..2 {
+        let expired = sides[side].expire(u64::MAX);
+        report_missing(&mut differences, sides, side, expired);
+    }
+
+    Ok(differences)
+}
+
+/// Count records of one side without a partner as missing on the other side
+fn report_missing(differences: &mut Differences, sides: &[Side; 2], side: usize, expired: Vec<(Key, Pending)>) {
+    for (key, pending) in expired {
+        differences.missing[side] += 1;
+
+        if differences.missing[side] as usize <= MAX_EXAMPLES {
+            differences.examples.push(format!(
+                "missing in {}: {} #{}, {}",
+                sides[1 - side].path,
+                sides[side].path,
+                pending.number,
+                describe(&key, pending.timestamp, pending.data.len())
+            ));
+        }
+    }
+}
+
+fn describe(key: &Key, timestamp: u64, size: usize) -> String {
+    let time = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(timestamp)).format("%Y-%m-%d %H:%M:%S%.6f");
+    let source = match key.source {
+        Source::Address(address) => address.to_string(),
+        Source::Input(input) => format!("input {}", input),
+    };
+
+    match key.value {
+        KeyValue::Sequence(sequence) => format!("{} from {} sequence {} size {}", time, source, sequence, size),
+        _ => format!("{} from {} size {}", time, source, size),
+    }
+}
+
+fn print_report(differences: &Differences, sides: &[Side; 2]) {
+    for side in sides {
+        println!("{}: {} records", side.path, side.count);
+    }
+
+    println!(
+        "{} aligned, {} with different payloads, {} missing in {}, {} missing in {}",
+        differences.matched,
+        differences.mismatched,
+        differences.missing[0],
+        sides[1].path,
+        differences.missing[1],
+        sides[0].path
+    );
+
+    for example in &differences.examples {
+        println!("  {}", example);
+    }
+
+    let skew = &differences.skew;
+    if skew.count == 0 {
+        return;
+    }
+
+    let mean = (skew.sum / skew.count as i128) as i64;
+    println!(
+        "Capture time skew of {} against {}: min {}, mean {}, max {}",
+        sides[1].path,
+        sides[0].path,
+        signed(skew.min),
+        signed(mean),
+        signed(skew.max)
+    );
+
+    let mut line = String::new();
+    for (i, count) in skew.buckets.iter().enumerate() {
+        match SKEW_BUCKETS.get(i) {
+            Some(bound) => line.push_str(&format!("<{:?}: {} ", bound, count)),
+            None => line.push_str(&format!(">={:?}: {}", SKEW_BUCKETS[i - 1], count)),
+        }
+    }
+    println!("  {}", line);
+}
+
+fn signed(nanos: i64) -> String {
+    let sign = if nanos < 0 { "-" } else { "" };
+    format!("{}{:?}", sign, Duration::from_nanos(nanos.unsigned_abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::format::{FileHeader, RecordingWriter};
+
+    const MS: u64 = 1_000_000;
+    const START: u64 = 1_700_000_000_000 * MS;
+
+    /// Recording of payloads captured at milliseconds after `START`, removed again on drop
+    struct Recording(String);
+
+    impl Recording {
+        fn new(name: &str, records: &[(u64, &[u8])]) -> Recording {
+            let path = env::temp_dir().join(format!("recorder-diff-test-{}-{}", process::id(), name));
+            let path = path.to_string_lossy().to_string();
+
+            let mut header = FileHeader::new(vec![]);
+            header.checksums = true;
+
+            let mut writer = RecordingWriter::create(File::create(&path).unwrap(), &mut header).unwrap();
+            for (ms, data) in records {
+                let record = Record {
+                    timestamp: START + ms * MS,
+                    monotonic: ms * MS,
+                    data: data.to_vec(),
+                    ..Default::default()
+                };
+                writer.write_record(&record).unwrap();
+            }
+            writer.finish().unwrap();
+
+            Recording(path)
+        }
+    }
+
+    impl Drop for Recording {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn diff(first: &Recording, second: &Recording, sequence: Option<&str>, window_ms: u64) -> Differences {
+        let mut sides = [Side::open(&first.0).unwrap(), Side::open(&second.0).unwrap()];
+        compare(&mut sides, sequence.map(|field| field.parse().unwrap()), window_ms * MS).unwrap()
+    }
+
+    #[test]
+    fn aligns_identical_payloads_across_skew_and_reordering() {
+        let first = Recording::new("same-1", &[(0, b"a"), (10, b"b"), (20, b"c"), (30, b"a")]);
+        let second = Recording::new("same-2", &[(2, b"b"), (3, b"a"), (22, b"c"), (33, b"a")]);
+
+        let differences = diff(&first, &second, None, 100);
+        assert_eq!((differences.matched, differences.mismatched, differences.missing), (4, 0, [0, 0]));
+        assert_eq!((differences.skew.min, differences.skew.max), (-8 * MS as i64, 3 * MS as i64));
+    }
+
+    #[test]
+    fn counts_records_without_partner_in_window_as_missing() {
+        let first = Recording::new("missing-1", &[(0, b"a"), (10, b"b"), (20, b"c")]);
+        let second = Recording::new("missing-2", &[(0, b"a"), (20, b"c"), (500, b"b")]);
+
+        let differences = diff(&first, &second, None, 100);
+        assert_eq!((differences.matched, differences.missing), (2, [1, 1]));
+        assert_eq!(differences.examples.len(), 2);
+    }
+
+    #[test]
+    fn aligns_by_sequence_numbers_and_reports_different_payloads() {
+        let first = Recording::new("seq-1", &[(0, b"\x00\x01one"), (10, b"\x00\x02two"), (20, b"\x03")]);
+        let second = Recording::new("seq-2", &[(1, b"\x00\x01one"), (11, b"\x00\x02TWO"), (21, b"\x03")]);
+
+        let differences = diff(&first, &second, Some("0:2"), 100);
+        assert_eq!((differences.matched, differences.mismatched, differences.missing), (3, 1, [0, 0]));
+
+        // Aligned by whole payloads the changed one has no partner
+        let differences = diff(&first, &second, None, 100);
+        assert_eq!((differences.matched, differences.mismatched, differences.missing), (2, 0, [1, 1]));
+    }
+
+    #[test]
+    fn parses_and_reads_sequence_fields() {
+        let big: SequenceField = "1:2".parse().unwrap();
+        let little: SequenceField = "1:4le".parse().unwrap();
+
+        assert_eq!(big.read(&[9, 0x01, 0x02]), Some(0x0102));
+        assert_eq!(little.read(&[9, 0x01, 0x02, 0, 0]), Some(0x0201));
+        assert_eq!(little.read(&[9, 0x01]), None);
+
+        for invalid in ["1:3", "x:2", "2", "1:8be"] {
+            assert!(invalid.parse::<SequenceField>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Export a recording to JSON Lines or CSV with one row per record, for analysis in other tools.
+//! JSON Lines exports can be imported back into a recording, see `import`.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Write},
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{format::RecordReader, utils::parse_hex};
+
+use super::{report_corruption, usage};
+
+const USAGE: &str = "export <recording> <output .jsonl or .csv> [hex|base64]";
+
+/// Encoding of payloads in exported rows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding, Error> {
+        match s {
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(usage(USAGE)),
+        }
+    }
+}
+
+/// One record as exported and imported.
+/// Imports only need a payload, missing times and inputs are filled in, see `import`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Row {
+    /// Number of the record, counted from 0
+    pub record: Option<u64>,
+    /// Capture time in nanoseconds since unix epoch, None for legacy recordings
+    pub timestamp_ns: Option<u64>,
+    /// Nanoseconds since the previous record
+    pub delta_ns: Option<u64>,
+    pub size: Option<usize>,
+    pub input: u16,
+    /// Address of the input, None if it has none
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+}
+
+impl Row {
+    /// Payload bytes, exactly one of `hex` and `base64` must be set
+    pub fn payload(&self) -> Result<Vec<u8>, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let payload = match (&self.hex, &self.base64) {
+            (Some(hex), None) => parse_hex(hex)?,
+            (None, Some(base64)) => STANDARD.decode(base64).map_err(|e| invalid(&format!("invalid base64: {}", e)))?,
+            _ => return Err(invalid("a row needs either hex or base64 payload")),
+        };
+
+        if let Some(size) = self.size.filter(|size| *size != payload.len()) {
+            return Err(invalid(&format!("size {} does not match payload of {} bytes", size, payload.len())));
+        }
+
+        Ok(payload)
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (recording, output, encoding) = match args {
+        [recording, output] => (recording, output, Encoding::default()),
+        [recording, output, encoding] => (recording, output, encoding.parse()?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    let csv = match output.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("jsonl") => false,
+        Some("csv") => true,
+        _ => return Err(usage(USAGE)),
+    };
+
+    let mut records = RecordReader::new(BufReader::with_capacity(131072, File::open(recording)?))?;
+    let mut writer = BufWriter::new(File::create_new(output)?);
+    let payload_column = match encoding {
+        Encoding::Hex => "hex",
+        Encoding::Base64 => "base64",
+    };
+
+    if csv {
+        writeln!(writer, "record,timestamp_ns,delta_ns,size,input,source,{}", payload_column)?;
+    }
+
+    let mut count = 0;
+    loop {
+        let number = records.position();
+        let Some(record) = records.next_record()? else {
+            break;
+        };
+
+        let payload = match encoding {
+            Encoding::Hex => record.data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            Encoding::Base64 => STANDARD.encode(&record.data),
+        };
+
+        let row = Row {
+            record: Some(number),
+            timestamp_ns: (record.timestamp > 0).then_some(record.timestamp),
+            delta_ns: Some(record.delta),
+            size: Some(record.data.len()),
+            input: record.input,
+            source: records.header.input_address(record.input).map(|address| address.to_string()),
+            ..Default::default()
+        };
+
+        if csv {
+            // No field can contain a comma or quote, so nothing needs quoting
+            let optional = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                number,
+                optional(row.timestamp_ns),
+                record.delta,
+                record.data.len(),
+                record.input,
+                row.source.as_deref().unwrap_or_default(),
+                payload
+            )?;
+        } else {
+            let row = match encoding {
+                Encoding::Hex => Row { hex: Some(payload), ..row },
+                Encoding::Base64 => Row { base64: Some(payload), ..row },
+            };
+            serde_json::to_writer(&mut writer, &row)?;
+            writeln!(writer)?;
+        }
+
+        count += 1;
+    }
+
+    writer.flush()?;
+    report_corruption(&records, recording);
+
+    println!("Exported {} records of {} to {}", count, recording, output);
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! Export a recording to a pcap or pcapng capture, for Wireshark and other capture tools.
+//! Recordings do not store addresses, every record gets the group and port of its input as
+//! destination and the input's interface as source.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, Write},
+};
+
+use crate::{
+    format::RecordReader,
+    pcap::{input_addresses, CaptureFormat, CaptureWriter},
+};
+
+use super::{report_corruption, usage};
+
+const USAGE: &str = "export-pcap <recording> <capture, pcapng if it ends in .pcapng>";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [recording, capture] = args else {
+        return Err(usage(USAGE));
+    };
+
+    let mut records = RecordReader::new(BufReader::new(File::open(recording)?))?;
+    let format = CaptureFormat::from_path(capture);
+    let mut writer = CaptureWriter::new(BufWriter::new(File::create_new(capture)?), format)?;
+
+    let mut count = 0;
+    while let Some(record) = records.next_record()? {
+        let (source, destination) = input_addresses(records.header.inputs.get(record.input as usize), None);
+        writer.write_packet(record.timestamp, source, destination, &record.data)?;
+        count += 1;
+    }
+
+    writer.get_mut().flush()?;
+
+    report_corruption(&records, recording);
+
+    println!("Exported {} records of {} to {:?} capture {}", count, recording, format, capture);
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Keep only the records of a recording whose payload matches byte predicates.
+//! Records keep their capture times, gaps left by dropped records replay as waits.
+
+use std::{io::Error, str::FromStr};
+
+use crate::utils::parse_hex;
+
+use super::{create_like, open_timed, report_corruption, usage};
+
+const USAGE: &str = "filter <recording> <output file> <predicate>...
+predicates, all of which must match: contains=<hex>, at<offset>=<hex>, input=<n>, !<predicate> negates";
+
+#[derive(Debug, Clone)]
+enum Predicate {
+    Contains(Vec<u8>),
+    /// Payload has these bytes at an offset
+    At(usize, Vec<u8>),
+    Input(u16),
+    Not(Box<Predicate>),
+}
+
+impl FromStr for Predicate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Predicate, Error> {
+        if let Some(predicate) = s.strip_prefix('!') {
+            return Ok(Predicate::Not(Box::new(predicate.parse()?)));
+        }
+
+        let (name, value) = s.split_once('=').ok_or_else(|| usage(USAGE))?;
+
+        match name {
+            "contains" => Ok(Predicate::Contains(parse_hex(value)?)),
+            "input" => value.parse().map(Predicate::Input).map_err(|_| usage(USAGE)),
+            _ => match name.strip_prefix("at").map(str::parse) {
+                Some(Ok(offset)) => Ok(Predicate::At(offset, parse_hex(value)?)),
+                _ => Err(usage(USAGE)),
+            },
+        }
+    }
+}
+
+impl Predicate {
+    fn matches(&self, input: u16, data: &[u8]) -> bool {
+        match self {
+            Predicate::Contains(bytes) => bytes.is_empty() || data.windows(bytes.len()).any(|window| window == bytes),
+            Predicate::At(offset, bytes) => data.get(*offset..).is_some_and(|rest| rest.starts_with(bytes)),
+            Predicate::Input(expected) => input == *expected,
+            Predicate::Not(predicate) => !predicate.matches(input, data),
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [recording, output, predicates @ ..] = args else {
+        return Err(usage(USAGE));
+    };
+
+    if predicates.is_empty() {
+        return Err(usage(USAGE));
+    }
+
+    let predicates = predicates
+        .iter()
+        .map(|predicate| predicate.parse())
+        .collect::<Result<Vec<Predicate>, Error>>()?;
+
+    let mut records = open_timed(recording)?;
+    let mut writer = create_like(output, &records.header)?;
+    let mut count = 0;
+
+    while let Some(record) = records.next_record()? {
+        if predicates.iter().all(|predicate| predicate.matches(record.input, &record.data)) {
+            writer.write_record(&record)?;
+            count += 1;
+        }
+    }
+
+    writer.finish()?;
+    report_corruption(&records, recording);
+
+    println!(
+        "Kept {} of {} records of {} in {}",
+        count,
+        records.position(),
+        recording,
+        output
+    );
+
+    Ok(())
+}
@@ -0,0 +1,280 @@
+//! Import records written as JSON Lines, in the format `export` writes, into a recording.
+//! Rows only need a payload, so test fixtures can be written by hand:
+//!
+//! ```text
+//! {"timestamp_ns": 1700000000000000000, "source": "239.0.0.1:5000", "hex": "01 02 03"}
+//! {"delta_ns": 250000000, "source": "239.0.0.1:5000", "hex": "04 05"}
+//! ```
+//!
+//! A row without a timestamp follows the previous one after `delta_ns`, the first starts now.
+//! Replays wait `delta_ns` before a row if it is given, otherwise the difference of the timestamps.
+//! Every source address becomes an input of the recording, rows without one keep their input number.
+//! Blank lines are skipped.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Error, ErrorKind},
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::{
+    constants::BUF_SIZE,
+    format::{Compression, Record},
+    recorder::Block,
+};
+
+use super::{create_like, export::Row, header_starting, usage};
+
+const USAGE: &str = "import <rows .jsonl> <recording> [compression: none, zstd or lz4, default none]";
+
+/// What a row's input is told apart by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Address(SocketAddr),
+    Input(u16),
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (rows, recording, compression) = match args {
+        [rows, recording] => (rows, recording, Compression::None),
+        [rows, recording, compression] => (rows, recording, compression.parse()?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    // First pass checks the rows and finds the sources and the start for the file header
+    let mut sources: Vec<Source> = vec![];
+    let mut start = None;
+    let mut timestamp = now();
+
+    for row in read_rows(rows)? {
+        let (line, row) = row?;
+        let source = source(&row).map_err(|e| at_line(rows, line, e))?;
+        let payload = row.payload().map_err(|e| at_line(rows, line, e))?;
+
+        if payload.len() > BUF_SIZE {
+            let e = Error::new(ErrorKind::InvalidData, format!("payload is larger than {} bytes", BUF_SIZE));
+            return Err(at_line(rows, line, e));
+        }
+
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+
+        timestamp = row.timestamp_ns.unwrap_or(timestamp + row.delta_ns.unwrap_or_default());
+        start.get_or_insert(timestamp);
+    }
+
+    let inputs = sources
+        .iter()
+        .map(|source| match source {
+            Source::Address(address) => serde_json::from_value(json!({
+                "mode": "udp",
+                "source_ip": address.ip().to_string(),
+                "source_port": address.port(),
+            })),
+            Source::Input(_) => serde_json::from_value(json!({ "mode": "file", "file_path": rows })),
+        })
+        .collect::<Result<Vec<Block>, _>>()?;
+
+    let start = start.unwrap_or(timestamp);
+    let mut writer = create_like(recording, &header_starting(inputs, start, compression))?;
+
+    let (mut timestamp, mut monotonic) = (start, 0);
+    let mut count = 0;
+
+    for row in read_rows(rows)? {
+        let (_, row) = row?;
+        let payload = row.payload()?;
+
+        // Deltas are kept exactly, they come from a monotonic clock which drifts from capture times
+        if count > 0 {
+            timestamp = row.timestamp_ns.unwrap_or(timestamp + row.delta_ns.unwrap_or_default());
+            monotonic = match row.delta_ns {
+                Some(delta) => monotonic + delta,
+                // Hand written timestamps may go back in time a little
+                None => timestamp.saturating_sub(start),
+            };
+        }
+
+        let source = source(&row)?;
+        let input = sources.iter().position(|known| *known == source).unwrap();
+
+        writer.write_record(&Record {
+            timestamp,
+            monotonic,
+            input: input as u16,
+            data: payload,
+            ..Default::default()
+        })?;
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    println!("Imported {} records of {} inputs from {} to {}", count, sources.len(), rows, recording);
+
+    Ok(())
+}
+
+/// Non blank rows with their line numbers, counted from 1
+fn read_rows(path: &str) -> Result<impl Iterator<Item = Result<(usize, Row), Error>> + '_, Error> {
+    let lines = BufReader::new(File::open(path)?).lines();
+
+    Ok(lines.enumerate().filter_map(move |(i, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map(|row| (i + 1, row)).map_err(|e| at_line(path, i + 1, e.into()))),
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+fn source(row: &Row) -> Result<Source, Error> {
+    match &row.source {
+        Some(address) => address
+            .parse()
+            .map(Source::Address)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid source address {:?}", address))),
+        None => Ok(Source::Input(row.input)),
+    }
+}
+
+fn at_line(path: &str, line: usize, e: Error) -> Error {
+    Error::new(e.kind(), format!("{} line {}: {}", path, line, e))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use super::*;
+    use crate::tools::{export, open_timed};
+
+    const MS: u64 = 1_000_000;
+    const START: u64 = 1_700_000_000_000_000_000;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("recorder-import-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn path(directory: &Path, name: &str) -> String {
+        directory.join(name).to_string_lossy().to_string()
+    }
+
+    /// Capture time, delta, source and payload of every record
+    fn read(recording: &str) -> Vec<(u64, u64, Option<SocketAddr>, Vec<u8>)> {
+        let mut records = open_timed(recording).unwrap();
+        let mut read = vec![];
+
+        while let Some(record) = records.next_record().unwrap() {
+            let source = records.header.input_address(record.input);
+            read.push((record.timestamp, record.delta, source, record.data));
+        }
+
+        read
+    }
+
+    #[test]
+    fn round_trips_jsonl_exports() {
+        let directory = directory("round-trip");
+        let recording = path(&directory, "original.rec");
+
+        let inputs = ["239.0.0.1:5000", "239.0.0.2:6000"]
+            .iter()
+            .map(|address| {
+                let address: SocketAddr = address.parse().unwrap();
+                serde_json::from_value(json!({
+                    "mode": "udp",
+                    "source_ip": address.ip().to_string(),
+                    "source_port": address.port(),
+                }))
+                .unwrap()
+            })
+            .collect();
+        let mut writer = create_like(&recording, &header_starting(inputs, START, Compression::None)).unwrap();
+
+        // The wall clock drifts from the monotonic one and steps back once, deltas follow the monotonic clock
+        let times = [(0, 0, 0), (100, 100, 1), (150, 170, 0), (140, 190, 1)];
+        for (i, (timestamp, monotonic, input)) in times.into_iter().enumerate() {
+            writer
+                .write_record(&Record {
+                    timestamp: START + timestamp * MS,
+                    monotonic: monotonic * MS,
+                    input,
+                    data: vec![i as u8; 10 + i],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let original = read(&recording);
+        assert_eq!(
+            original.iter().map(|(timestamp, delta, _, _)| (*timestamp, *delta)).collect::<Vec<_>>(),
+            [(START, 0), (START + 100 * MS, 100 * MS), (START + 150 * MS, 70 * MS), (START + 140 * MS, 20 * MS)]
+        );
+
+        for encoding in ["hex", "base64"] {
+            let rows = path(&directory, &format!("{}.jsonl", encoding));
+            let imported = path(&directory, &format!("{}.rec", encoding));
+
+            export::run(&[recording.clone(), rows.clone(), encoding.to_string()]).unwrap();
+            run(&[rows, imported.clone(), "zstd".to_string()]).unwrap();
+
+            assert_eq!(read(&imported), original, "{}", encoding);
+            assert_eq!(open_timed(&imported).unwrap().header.compression, Compression::Zstd);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn fills_in_times_of_hand_written_rows() {
+        let directory = directory("hand-written");
+        let rows = path(&directory, "rows.jsonl");
+        let recording = path(&directory, "rows.rec");
+        let address: SocketAddr = "239.0.0.1:5000".parse().unwrap();
+
+        let lines = [
+            json!({ "timestamp_ns": START, "source": address, "hex": "01 02 03" }),
+            json!({ "delta_ns": 250 * MS, "source": address, "hex": "04 05" }),
+            json!({ "timestamp_ns": START + 1000 * MS, "source": address, "base64": "Bg==" }),
+            json!({ "delta_ns": 5 * MS, "timestamp_ns": START + 1010 * MS, "hex": "07" }),
+        ];
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        fs::write(&rows, text.join("\n\n")).unwrap();
+
+        run(&[rows.clone(), recording.clone()]).unwrap();
+
+        // Timestamps follow deltas, deltas follow timestamps, unless both are given
+        assert_eq!(
+            read(&recording),
+            [
+                (START, 0, Some(address), vec![1, 2, 3]),
+                (START + 250 * MS, 250 * MS, Some(address), vec![4, 5]),
+                (START + 1000 * MS, 750 * MS, Some(address), vec![6]),
+                (START + 1010 * MS, 5 * MS, None, vec![7]),
+            ]
+        );
+
+        // Errors name the line
+        fs::write(&rows, format!("{}\n{{\"hex\": \"0\"}}\n", text[0])).unwrap();
+        let error = run(&[rows.clone(), path(&directory, "bad.rec")]).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Import the UDP packets of a pcap or pcapng capture into a recording.
+//! Every destination group and port becomes an input of the recording, so records keep
+//! which stream they belong to.
+
+use std::{
+    fs::File,
+    io::{BufReader, Error},
+    net::SocketAddrV4,
+};
+
+use serde_json::json;
+
+use crate::{
+    constants::BUF_SIZE,
+    format::{Compression, Record},
+    pcap::CaptureReader,
+    recorder::Block,
+};
+
+use super::{create_like, header_starting, usage};
+
+const USAGE: &str = "import-pcap <capture> <recording> [compression: none, zstd or lz4, default none]";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (capture, recording, compression) = match args {
+        [capture, recording] => (capture, recording, Compression::None),
+        [capture, recording, compression] => (capture, recording, compression.parse()?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    // First pass finds the streams and the start of the capture for the file header
+    let mut packets = CaptureReader::new(BufReader::new(File::open(capture)?))?;
+    let mut destinations: Vec<SocketAddrV4> = vec![];
+    let mut start = None;
+
+    while let Some(packet) = packets.next_packet()? {
+        if !destinations.contains(&packet.destination) {
+            destinations.push(packet.destination);
+        }
+        start.get_or_insert(packet.timestamp);
+    }
+
+    let inputs = destinations
+        .iter()
+        .map(|destination| {
+            serde_json::from_value(json!({
+                "mode": "udp",
+                "source_ip": destination.ip().to_string(),
+                "source_port": destination.port(),
+            }))
+        })
+        .collect::<Result<Vec<Block>, _>>()?;
+
+    let start = start.unwrap_or_default();
+    let mut writer = create_like(recording, &header_starting(inputs, start, compression))?;
+
+    let mut packets = CaptureReader::new(BufReader::new(File::open(capture)?))?;
+    let (mut count, mut too_large) = (0, 0);
+
+    while let Some(packet) = packets.next_packet()? {
+        if packet.payload.len() > BUF_SIZE {
+            too_large += 1;
+            continue;
+        }
+
+        let input = destinations.iter().position(|destination| *destination == packet.destination).unwrap();
+        // Captures merged from several interfaces may go back in time a little
+        let monotonic = packet.timestamp.saturating_sub(start);
+
+        writer.write_record(&Record {
+            timestamp: packet.timestamp,
+            monotonic,
+            input: input as u16,
+            data: packet.payload,
+            ..Default::default()
+        })?;
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    if packets.skipped > 0 {
+        println!("Skipped {} packets of {} which are not UDP over IPv4", packets.skipped, capture);
+    }
+
+    if too_large > 0 {
+        println!("Skipped {} packets of {} larger than {} bytes", too_large, capture, BUF_SIZE);
+    }
+
+    println!(
+        "Imported {} packets of {} streams from {:?} capture {} to {}",
+        count,
+        destinations.len(),
+        packets.format,
+        capture,
+        recording
+    );
+
+    Ok(())
+}
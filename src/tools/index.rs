@@ -0,0 +1,44 @@
+//! Rebuild the sidecar index of a recording
+
+use std::{
+    fs::File,
+    io::{BufReader, Error},
+};
+
+use crate::{
+    format::RecordReader,
+    index::{index_path, Index},
+};
+
+use super::{report_corruption, usage};
+
+const USAGE: &str = "index <recording> [every records, default 10000] [every ms, default 1000]";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (recording, every_records, every_ms) = match args {
+        [recording] => (recording, 10000, 1000),
+        [recording, records] => (recording, parse(records)?, 1000),
+        [recording, records, ms] => (recording, parse(records)?, parse(ms)?),
+        _ => return Err(usage(USAGE)),
+    };
+
+    let mut records = RecordReader::new(BufReader::new(File::open(recording)?))?;
+    let index = Index::build(&mut records, every_records, every_ms)?;
+    index.save(recording)?;
+
+    report_corruption(&records, recording);
+
+    println!(
+        "Indexed {} records of {} with {} entries in {}",
+        records.position(),
+        recording,
+        index.entries.len(),
+        index_path(recording)
+    );
+
+    Ok(())
+}
+
+fn parse(arg: &str) -> Result<u64, Error> {
+    arg.parse().map_err(|_| usage(USAGE))
+}
@@ -0,0 +1,272 @@
+//! Report what a recording holds: counts, time span, packet sizes, gaps, rate over time and
+//! inputs, as text or as JSON for scripts
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, Error},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{constants::BUF_SIZE, format::RecordReader};
+
+use super::usage;
+
+const USAGE: &str = "inspect <recording> [--json]";
+/// Rate samples are grouped into intervals of whole seconds, so there are at most this many
+const MAX_RATE_SAMPLES: u64 = 60;
+/// Width of the longest histogram bar in text output
+const BAR_WIDTH: u64 = 40;
+
+#[derive(Serialize, Debug)]
+struct Report {
+    recording: String,
+    version: u16,
+    compression: String,
+    created_at: String,
+    host: String,
+    file_bytes: u64,
+    records: u64,
+    payload_bytes: u64,
+    /// Capture times, None for legacy recordings which have none
+    first_timestamp: Option<String>,
+    last_timestamp: Option<String>,
+    duration_s: f64,
+    min_size: u64,
+    max_size: u64,
+    mean_size: f64,
+    size_histogram: Vec<SizeBucket>,
+    max_gap_s: f64,
+    /// Record following the largest gap
+    max_gap_record: u64,
+    /// Length of the rate intervals
+    rate_interval_s: u64,
+    rate: Vec<RateSample>,
+    sources: Vec<Source>,
+    corrupt_places: u64,
+    corrupt_bytes: u64,
+    /// Bytes of a partial record at the end
+    partial_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct SizeBucket {
+    /// Payloads of at most this many bytes and more than the previous bucket's
+    up_to: usize,
+    records: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct RateSample {
+    /// Seconds since the first record
+    offset_s: u64,
+    packets: u64,
+    packets_per_s: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct Source {
+    input: u16,
+    mode: String,
+    address: Option<String>,
+    records: u64,
+    bytes: u64,
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (recording, json) = match args {
+        [recording] => (recording, false),
+        [recording, flag] if flag == "--json" => (recording, true),
+        _ => return Err(usage(USAGE)),
+    };
+
+    let report = inspect(recording)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_text(&report);
+    }
+
+    Ok(())
+}
+
+fn inspect(recording: &str) -> Result<Report, Error> {
+    let mut records = RecordReader::new(BufReader::with_capacity(131072, File::open(recording)?))?;
+
+    // Powers of two up to the largest record
+    let bounds: Vec<usize> = (6..).map(|bits| 1 << bits).take_while(|bound| *bound < BUF_SIZE).chain([BUF_SIZE]).collect();
+    let mut histogram = vec![0; bounds.len()];
+
+    let (mut count, mut payload_bytes, mut min_size, mut max_size) = (0, 0, u64::MAX, 0);
+    let (mut first_timestamp, mut last_timestamp, mut duration) = (0, 0, 0);
+    let (mut max_gap, mut max_gap_record) = (0, 0);
+    let mut per_second: Vec<u64> = vec![];
+    let mut sources: Vec<Source> = vec![];
+
+    while let Some(record) = records.next_record()? {
+        let size = record.data.len() as u64;
+
+        if count == 0 {
+            first_timestamp = record.timestamp;
+        } else if record.delta > max_gap {
+            max_gap = record.delta;
+            max_gap_record = count;
+        }
+
+        count += 1;
+        payload_bytes += size;
+        min_size = min_size.min(size);
+        max_size = max_size.max(size);
+        last_timestamp = record.timestamp;
+        duration = record.elapsed;
+
+        let bucket = bounds.iter().position(|bound| record.data.len() <= *bound).unwrap_or(bounds.len() - 1);
+        histogram[bucket] += 1;
+
+        let second = (record.elapsed / 1_000_000_000) as usize;
+        if per_second.len() <= second {
+            per_second.resize(second + 1, 0);
+        }
+        per_second[second] += 1;
+
+        let source = match sources.iter().position(|source| source.input == record.input) {
+            Some(source) => source,
+            None => {
+                let input = records.header.inputs.get(record.input as usize);
+                sources.push(Source {
+                    input: record.input,
+                    mode: input.map(|input| format!("{:?}", input.mode)).unwrap_or_default(),
+                    address: records.header.input_address(record.input).map(|address| address.to_string()),
+                    records: 0,
+                    bytes: 0,
+                });
+                sources.len() - 1
+            }
+        };
+        sources[source].records += 1;
+        sources[source].bytes += size;
+    }
+
+    sources.sort_by_key(|source| source.input);
+
+    let interval = (per_second.len() as u64).div_ceil(MAX_RATE_SAMPLES).max(1);
+    let rate = per_second
+        .chunks(interval as usize)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let packets = chunk.iter().sum::<u64>();
+            RateSample {
+                offset_s: i as u64 * interval,
+                packets,
+                packets_per_s: packets as f64 / chunk.len() as f64,
+            }
+        })
+        .collect();
+
+    let file_bytes = fs::metadata(recording)?.len();
+    let timestamp = |timestamp: u64| {
+        (timestamp > 0).then(|| DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(timestamp)).to_rfc3339())
+    };
+
+    Ok(Report {
+        recording: recording.to_string(),
+        version: records.header.version,
+        compression: format!("{:?}", records.header.compression),
+        created_at: records.header.created_at.clone(),
+        host: records.header.host.clone(),
+        file_bytes,
+        records: count,
+        payload_bytes,
+        first_timestamp: timestamp(first_timestamp),
+        last_timestamp: timestamp(last_timestamp),
+        duration_s: duration as f64 / 1e9,
+        min_size: if count > 0 { min_size } else { 0 },
+        max_size,
+        mean_size: if count > 0 { payload_bytes as f64 / count as f64 } else { 0.0 },
+        size_histogram: bounds
+            .into_iter()
+            .zip(histogram)
+            .map(|(up_to, records)| SizeBucket { up_to, records })
+            .collect(),
+        max_gap_s: max_gap as f64 / 1e9,
+        max_gap_record,
+        rate_interval_s: interval,
+        rate,
+        sources,
+        corrupt_places: records.corruption.bad_records,
+        corrupt_bytes: records.corruption.skipped_bytes,
+        partial_bytes: file_bytes.saturating_sub(records.offset()),
+    })
+}
+
+fn print_text(report: &Report) {
+    println!("{}", report.recording);
+    println!(
+        "  format version {}, compression {}, created {} on {:?}",
+        report.version, report.compression, report.created_at, report.host
+    );
+    println!(
+        "  {} records, {} payload bytes, {} bytes on disk",
+        report.records, report.payload_bytes, report.file_bytes
+    );
+
+    if let (Some(first), Some(last)) = (&report.first_timestamp, &report.last_timestamp) {
+        println!("  first {}", first);
+        println!("  last  {}", last);
+    }
+    println!("  duration {:.3} s", report.duration_s);
+    println!(
+        "  max gap {:.6} s before record {}",
+        report.max_gap_s, report.max_gap_record
+    );
+
+    if report.corrupt_places > 0 || report.partial_bytes > 0 {
+        println!(
+            "  {} corrupt bytes in {} places, {} bytes of partial record at the end",
+            report.corrupt_bytes, report.corrupt_places, report.partial_bytes
+        );
+    }
+
+    println!();
+    println!(
+        "Packet sizes: min {}, max {}, mean {:.1}",
+        report.min_size, report.max_size, report.mean_size
+    );
+    let largest = report.size_histogram.iter().map(|bucket| bucket.records).max().unwrap_or(0);
+    for bucket in report.size_histogram.iter().filter(|bucket| bucket.records > 0) {
+        println!("  <= {:>6} {:>10} {}", bucket.up_to, bucket.records, bar(bucket.records, largest));
+    }
+
+    println!();
+    println!("Packets per second, every {} s:", report.rate_interval_s);
+    let fastest = report.rate.iter().map(|sample| sample.packets).max().unwrap_or(0);
+    for sample in &report.rate {
+        println!(
+            "  +{:>7} s {:>12.1} {}",
+            sample.offset_s,
+            sample.packets_per_s,
+            bar(sample.packets, fastest)
+        );
+    }
+
+    println!();
+    println!("Sources:");
+    for source in &report.sources {
+        println!(
+            "  input {:>3} {:<10} {:<22} {:>10} records {:>12} bytes",
+            source.input,
+            source.mode,
+            source.address.as_deref().unwrap_or("-"),
+            source.records,
+            source.bytes
+        );
+    }
+}
+
+fn bar(value: u64, largest: u64) -> String {
+    let width = if largest == 0 { 0 } else { (value * BAR_WIDTH).div_ceil(largest) };
+    "#".repeat(width as usize)
+}
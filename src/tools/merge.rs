@@ -0,0 +1,50 @@
+//! Merge recordings into one in capture time order.
+//! Inputs of all recordings are kept in the merged file header, so every record still
+//! tells which input, and so which group and port, it was captured from.
+//! The merged recording is compressed like the first one.
+
+use std::io::Error;
+
+use crate::merge::Merger;
+
+use super::{create_like, header_starting, report_corruption, usage};
+
+const USAGE: &str = "merge <output file> <recording> <recording>...";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [output, recordings @ ..] = args else {
+        return Err(usage(USAGE));
+    };
+
+    if recordings.len() < 2 {
+        return Err(usage(USAGE));
+    }
+
+    let mut merger = Merger::open(recordings)?;
+    let offsets = merger.input_offsets();
+
+    let start = merger.peek_timestamp().unwrap_or_default();
+    let compression = merger.readers[0].header.compression;
+    let mut writer = create_like(output, &header_starting(merger.inputs(recordings)?, start, compression))?;
+
+    // Monotonic times of different recordings cannot be compared, they restart from the merged start
+    let mut count = 0;
+    while let Some((source, mut record)) = merger.next_record()? {
+        record.monotonic = record.timestamp.saturating_sub(start);
+        record.input += offsets[source] as u16;
+
+        writer.write_record(&record)?;
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    for (records, recording) in merger.readers.iter().zip(recordings) {
+        report_corruption(records, recording);
+        println!("{} records from {}", records.position(), recording);
+    }
+
+    println!("Merged {} records of {} recordings to {}", count, recordings.len(), output);
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Offline tools working on recordings, run as `recorder <command> [args]`
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    format::{Compression, FileHeader, RecordReader, RecordingWriter},
+    recorder::Block,
+};
+
+pub mod bench_bus;
+pub mod bench_write;
+pub mod convert;
+pub mod diff;
+pub mod export;
+pub mod export_pcap;
+pub mod filter;
+pub mod import;
+pub mod import_pcap;
+pub mod index;
+pub mod inspect;
+pub mod merge;
+pub mod repair;
+pub mod slice;
+pub mod split;
+pub mod view;
+
+/// Run the tool named by the first argument.
+/// Returns None if the first argument is not a tool, so it can be treated as a settings path.
+pub fn run(args: &[String]) -> Option<Result<(), Error>> {
+    let (command, args) = args.split_first()?;
+
+    let result = match command.as_str() {
+        "bench-bus" => bench_bus::run(args),
+        "bench-write" => bench_write::run(args),
+        "convert" => convert::run(args),
+        "diff" => diff::run(args),
+        "export" => export::run(args),
+        "export-pcap" => export_pcap::run(args),
+        "filter" => filter::run(args),
+        "import" => import::run(args),
+        "import-pcap" => import_pcap::run(args),
+        "index" => index::run(args),
+        "inspect" => inspect::run(args),
+        "merge" => merge::run(args),
+        "repair" => repair::run(args),
+        "slice" => slice::run(args),
+        "split" => split::run(args),
+        "view" => view::run(args),
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+/// Error for wrong command line usage
+pub fn usage(text: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("usage: recorder {}", text))
+}
+
+/// Open a recording whose records are written out again with their capture times.
+/// Legacy recordings have none, they need converting first.
+pub fn open_timed(recording: &str) -> Result<RecordReader<BufReader<File>>, Error> {
+    let records = RecordReader::new(BufReader::with_capacity(131072, File::open(recording)?))?;
+
+    if records.header.is_legacy() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is a legacy recording without capture times, convert it first", recording),
+        ));
+    }
+
+    Ok(records)
+}
+
+/// Create a recording with the inputs, creation time and compression of `source`
+pub fn create_like(path: &str, source: &FileHeader) -> Result<RecordingWriter<BufWriter<File>>, Error> {
+    let mut header = FileHeader::new(source.inputs.clone());
+    header.created_at = source.created_at.clone();
+    header.checksums = true;
+    header.compression = source.compression;
+
+    RecordingWriter::create(BufWriter::new(File::create_new(path)?), &mut header)
+}
+
+/// Header for `create_like` of a recording of `inputs` whose first record was captured at `start`
+pub fn header_starting(inputs: Vec<Block>, start: u64, compression: Compression) -> FileHeader {
+    let mut header = FileHeader::new(inputs);
+    header.created_at = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(start)).to_rfc3339();
+    header.compression = compression;
+
+    header
+}
+
+/// Print how much corrupt data the reader skipped, if any
+pub fn report_corruption<R>(records: &RecordReader<R>, recording: &str) {
+    if records.corruption.bad_records > 0 {
+        println!(
+            "Skipped {} bytes in {} corrupt places of {}",
+            records.corruption.skipped_bytes, records.corruption.bad_records, recording
+        );
+    }
+}
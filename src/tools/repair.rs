@@ -0,0 +1,77 @@
+//! Remove the partial record a killed recorder left at the end of a recording
+
+use std::io::Error;
+
+use crate::format::{quarantine_path, repair_tail, TailRepair};
+
+use super::usage;
+
+const USAGE: &str = "repair <recording> [--quarantine]";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (recording, repair) = match args {
+        [recording] => (recording, TailRepair::Truncate),
+        [recording, flag] if flag == "--quarantine" => (recording, TailRepair::Quarantine),
+        _ => return Err(usage(USAGE)),
+    };
+
+    match (repair_tail(recording, repair)?, repair) {
+        (None, _) => println!("{} has no partial record at the end", recording),
+        (Some(tail), TailRepair::Truncate) => println!(
+            "Truncated {} bytes of partial record at offset {} from {}",
+            tail.length, tail.offset, recording
+        ),
+        (Some(tail), TailRepair::Quarantine) => println!(
+            "Moved {} bytes of partial record from {} to {}",
+            tail.length,
+            recording,
+            quarantine_path(recording, tail.offset)
+        ),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::format::{FileHeader, Record, RecordReader, RecordingWriter};
+
+    #[test]
+    fn quarantines_the_partial_record_of_a_recording() {
+        let path = env::temp_dir().join(format!("recorder-repair-tool-{}.rec", process::id()));
+        let path = path.to_string_lossy().to_string();
+
+        let mut header = FileHeader::new(vec![]);
+        header.checksums = true;
+        let mut writer = RecordingWriter::create(vec![], &mut header).unwrap();
+        writer
+            .write_record(&Record {
+                timestamp: header.created_at_nanos().unwrap(),
+                data: b"whole".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut file = writer.finish().unwrap();
+        let end = file.len() as u64;
+        file.extend_from_slice(&[0; 12]);
+        fs::write(&path, file).unwrap();
+
+        assert!(run(&[path.clone(), "--keep".to_string()]).is_err());
+        run(&[path.clone(), "--quarantine".to_string()]).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), end);
+        assert_eq!(fs::read(quarantine_path(&path, end)).unwrap(), [0; 12]);
+        let mut records = RecordReader::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(records.next_record().unwrap().unwrap().data, b"whole");
+
+        // Nothing left to repair
+        run(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), end);
+
+        fs::remove_file(quarantine_path(&path, end)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+//! Cut a recording to a time or record range.
+//! Records keep their capture times, so the slice replays with the same timing.
+
+use std::{
+    io::{Error, ErrorKind, Read, Seek},
+    str::FromStr,
+};
+
+use crate::{
+    format::{Record, RecordReader},
+    index::Index,
+    replay::{WindowBound, WindowPosition},
+};
+
+use super::{create_like, open_timed, report_corruption, usage};
+
+const USAGE: &str = "slice <recording> <output file> <from> <to>
+bounds are a time of day 09:15[:30.250], an offset from the first record +90s, a record number #1000 or -";
+
+/// Start or end of a slice, the end is exclusive
+#[derive(Debug, Clone, Copy)]
+pub(super) enum SliceBound {
+    Window(WindowBound),
+    /// Records before this many are outside
+    Record(u64),
+}
+
+impl SliceBound {
+    /// Parse a command line bound, `-` leaves the slice open on that side
+    pub(super) fn parse_arg(arg: &str) -> Result<Option<SliceBound>, Error> {
+        (arg != "-").then(|| arg.parse()).transpose()
+    }
+}
+
+impl FromStr for SliceBound {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SliceBound, Error> {
+        match s.strip_prefix('#') {
+            Some(record) => record
+                .parse()
+                .map(SliceBound::Record)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid record number {:?}", s))),
+            None => s.parse().map(SliceBound::Window),
+        }
+    }
+}
+
+/// Slice bound resolved against the first record
+#[derive(Debug, Clone, Copy)]
+pub(super) enum SlicePosition {
+    Window(WindowPosition),
+    Record(u64),
+}
+
+impl SlicePosition {
+    pub(super) fn resolve(bound: SliceBound, first: &Record) -> Result<SlicePosition, Error> {
+        match bound {
+            SliceBound::Window(bound) => bound.resolve(first).map(SlicePosition::Window),
+            SliceBound::Record(record) => Ok(SlicePosition::Record(record)),
+        }
+    }
+
+    /// Whether the record numbered `number` lies at or after this position
+    pub(super) fn reached(&self, record: &Record, number: u64) -> bool {
+        match self {
+            SlicePosition::Window(position) => position.reached(record),
+            SlicePosition::Record(record) => number >= *record,
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [recording, output, from, to] = args else {
+        return Err(usage(USAGE));
+    };
+
+    let (from, to) = (SliceBound::parse_arg(from)?, SliceBound::parse_arg(to)?);
+
+    let mut records = open_timed(recording)?;
+    let mut writer = create_like(output, &records.header)?;
+
+    let Some(first) = records.next_record()? else {
+        writer.finish()?;
+        println!("{} has no records", recording);
+        return Ok(());
+    };
+
+    let start = from.map(|from| SlicePosition::resolve(from, &first)).transpose()?;
+    let end = to.map(|to| SlicePosition::resolve(to, &first)).transpose()?;
+
+    seek_before(&mut records, recording, start)?;
+
+    let mut count = 0;
+    loop {
+        let number = records.position();
+        let Some(record) = records.next_record()? else {
+            break;
+        };
+
+        if start.is_some_and(|start| !start.reached(&record, number)) {
+            continue;
+        }
+
+        if end.is_some_and(|end| end.reached(&record, number)) {
+            break;
+        }
+
+        writer.write_record(&record)?;
+        count += 1;
+    }
+
+    writer.finish()?;
+    report_corruption(&records, recording);
+
+    println!("Sliced {} records of {} to {}", count, recording, output);
+
+    Ok(())
+}
+
+/// Move to the first record, or close before `start` if the recording has an index.
+/// Elapsed times stay right after seeking.
+pub(super) fn seek_before<R: Read + Seek>(
+    records: &mut RecordReader<R>,
+    recording: &str,
+    start: Option<SlicePosition>,
+) -> Result<(), Error> {
+    let (start_record, start_position) = match start {
+        Some(SlicePosition::Record(record)) => (Some(record), None),
+        Some(SlicePosition::Window(position)) => (None, Some(position)),
+        None => (None, None),
+    };
+
+    let index = Index::load(recording).ok();
+    let entry = index.as_ref().and_then(|index| index.find(start_record, start_position.as_ref()));
+    match entry.filter(|entry| entry.record > 0) {
+        Some(entry) => records.seek_to(entry),
+        None => records.rewind(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::PathBuf,
+        process,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use chrono::{DateTime, Local};
+
+    use super::*;
+    use crate::{
+        format::Compression,
+        tools::{create_like, header_starting},
+    };
+
+    const MS: u64 = 1_000_000;
+    const START: u64 = 1_700_000_000_000_000_000;
+
+    /// Recording of ten records 100 ms apart, each payload is the record number
+    fn recording(name: &str) -> (PathBuf, String) {
+        let directory = env::temp_dir().join(format!("recorder-slice-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("in.rec").to_string_lossy().to_string();
+
+        let mut writer = create_like(&path, &header_starting(vec![], START, Compression::None)).unwrap();
+        for i in 0..10 {
+            writer
+                .write_record(&Record {
+                    timestamp: START + i * 100 * MS,
+                    monotonic: i * 100 * MS,
+                    data: vec![i as u8],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        (directory, path)
+    }
+
+    fn slice(path: &str, from: &str, to: &str) -> Vec<u8> {
+        let output = format!("{}.slice", path);
+        run(&[path.to_string(), output.clone(), from.to_string(), to.to_string()]).unwrap();
+
+        let mut records = open_timed(&output).unwrap();
+        let mut payloads = vec![];
+        while let Some(record) = records.next_record().unwrap() {
+            payloads.push(record.data[0]);
+        }
+
+        fs::remove_file(output).unwrap();
+        payloads
+    }
+
+    #[test]
+    fn slices_from_inclusive_to_exclusive_bounds() {
+        let (directory, path) = recording("bounds");
+        let third = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(START + 300 * MS));
+        let third = third.format("%H:%M:%S%.3f").to_string();
+
+        // Same slices read from the start or seeking with an index
+        for indexed in [false, true] {
+            if indexed {
+                let mut records = open_timed(&path).unwrap();
+                Index::build(&mut records, 2, 0).unwrap().save(&path).unwrap();
+            }
+
+            assert_eq!(slice(&path, "+200ms", "+500ms"), [2, 3, 4], "indexed {}", indexed);
+            assert_eq!(slice(&path, "#3", "#6"), [3, 4, 5], "indexed {}", indexed);
+            assert_eq!(slice(&path, "-", "#2"), [0, 1], "indexed {}", indexed);
+            assert_eq!(slice(&path, "+850ms", "-"), [9], "indexed {}", indexed);
+            assert_eq!(slice(&path, "#4", "+0.6s"), [4, 5], "indexed {}", indexed);
+            assert_eq!(slice(&path, &third, "#5"), [3, 4], "indexed {}", indexed);
+            assert!(slice(&path, "#5", "#5").is_empty(), "indexed {}", indexed);
+            assert!(slice(&path, "+2s", "-").is_empty(), "indexed {}", indexed);
+        }
+
+        assert!(SliceBound::parse_arg("#-1").is_err());
+        assert!(SliceBound::parse_arg("9h").is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn seeks_to_the_last_index_entry_before_the_start() {
+        let (directory, path) = recording("seek");
+        let mut records = open_timed(&path).unwrap();
+        let first = records.next_record().unwrap().unwrap();
+
+        let start = |bound: &str| Some(SlicePosition::resolve(bound.parse().unwrap(), &first).unwrap());
+        let next = |records: &mut RecordReader<_>| {
+            let number = records.position();
+            let record = records.next_record().unwrap().unwrap();
+            (number, record.data[0], record.elapsed)
+        };
+
+        // Without an index reading starts over
+        seek_before(&mut records, &path, start("#5")).unwrap();
+        assert_eq!(next(&mut records), (0, 0, 0));
+
+        let index = Index::build(&mut records, 2, 0).unwrap();
+        index.save(&path).unwrap();
+
+        // Elapsed times stay right after seeking
+        seek_before(&mut records, &path, start("#5")).unwrap();
+        assert_eq!(next(&mut records), (4, 4, 400 * MS));
+        assert_eq!(next(&mut records), (5, 5, 500 * MS));
+
+        seek_before(&mut records, &path, start("+600ms")).unwrap();
+        assert_eq!(next(&mut records), (4, 4, 400 * MS));
+
+        seek_before(&mut records, &path, start("+100ms")).unwrap();
+        assert_eq!(next(&mut records), (0, 0, 0));
+
+        seek_before(&mut records, &path, None).unwrap();
+        assert_eq!(next(&mut records), (0, 0, 0));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
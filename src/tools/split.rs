@@ -0,0 +1,207 @@
+//! Split a recording into several, by hour of capture, by size or by input.
+//! Parts are named after the output file with the part inserted before its extension,
+//! `out.2024-05-01_09.rec`, `out.0001.rec` or `out.input0.rec`. Every part keeps the inputs of
+//! the recording and its records their capture times, so parts replay with the same timing.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Error},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::format::RecordingWriter;
+
+use super::{create_like, open_timed, report_corruption, usage};
+
+const USAGE: &str = "split <recording> <output file> hour|size <megabytes>|input";
+
+#[derive(Debug, Clone, Copy)]
+enum SplitBy {
+    /// Local hour of capture
+    Hour,
+    /// Bytes of records per part
+    Size(u64),
+    Input,
+}
+
+struct Part {
+    name: String,
+    path: String,
+    writer: RecordingWriter<BufWriter<File>>,
+    records: u64,
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let (recording, output, split_by) = match args {
+        [recording, output, by] if by == "hour" => (recording, output, SplitBy::Hour),
+        [recording, output, by] if by == "input" => (recording, output, SplitBy::Input),
+        [recording, output, by, megabytes] if by == "size" => {
+            let megabytes: u64 = megabytes.parse().map_err(|_| usage(USAGE))?;
+            (recording, output, SplitBy::Size(megabytes.max(1) * 1024 * 1024))
+        }
+        _ => return Err(usage(USAGE)),
+    };
+
+    let mut records = open_timed(recording)?;
+    let mut parts: Vec<Part> = vec![];
+    let mut sequence = 0;
+
+    while let Some(record) = records.next_record()? {
+        let name = match split_by {
+            SplitBy::Hour => DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(record.timestamp))
+                .format("%Y-%m-%d_%H")
+                .to_string(),
+            SplitBy::Size(limit) => {
+                if parts.last().is_some_and(|part| part.writer.size >= limit) {
+                    sequence += 1;
+                }
+                format!("{:04}", sequence)
+            }
+            SplitBy::Input => format!("input{}", record.input),
+        };
+
+        // Parts stay open, a clock step may go back to an earlier hour
+        let part = match parts.iter().position(|part| part.name == name) {
+            Some(part) => part,
+            None => {
+                let path = part_path(output, &name);
+                parts.push(Part {
+                    writer: create_like(&path, &records.header)?,
+                    name,
+                    path,
+                    records: 0,
+                });
+                parts.len() - 1
+            }
+        };
+
+        parts[part].writer.write_record(&record)?;
+        parts[part].records += 1;
+    }
+
+    report_corruption(&records, recording);
+
+    let count = parts.len();
+    for part in parts {
+        part.writer.finish()?;
+        println!("{} records to {}", part.records, part.path);
+    }
+
+    println!("Split {} records of {} into {} parts", records.position(), recording, count);
+
+    Ok(())
+}
+
+/// Output path with the part inserted before the extension
+fn part_path(output: &str, part: &str) -> String {
+    let path = Path::new(output);
+
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!("{}.{}.{}", stem.to_string_lossy(), part, extension.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{}.{}", output, part),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        constants::BUF_SIZE,
+        format::{Compression, Record},
+        tools::header_starting,
+    };
+
+    const SECOND: u64 = 1_000_000_000;
+    const START: u64 = 1_700_000_000 * SECOND;
+
+    /// Recording of two inputs with records of `size` bytes captured at `times`, each payload starts with its number
+    fn recording(name: &str, times: &[u64], size: usize) -> (PathBuf, String) {
+        let directory = env::temp_dir().join(format!("recorder-split-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("in.rec").to_string_lossy().to_string();
+
+        let inputs = (0..2)
+            .map(|port| serde_json::from_value(json!({ "mode": "udp", "source_port": 5000 + port })).unwrap())
+            .collect();
+        let mut writer = create_like(&path, &header_starting(inputs, START, Compression::None)).unwrap();
+        for (i, time) in times.iter().enumerate() {
+            writer
+                .write_record(&Record {
+                    timestamp: START + time,
+                    monotonic: *time,
+                    input: i as u16 % 2,
+                    data: vec![i as u8; size],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        (directory, path)
+    }
+
+    fn payloads(path: &str) -> Vec<u8> {
+        let mut records = open_timed(path).unwrap();
+        let mut payloads = vec![];
+        while let Some(record) = records.next_record().unwrap() {
+            payloads.push(record.data[0]);
+        }
+
+        payloads
+    }
+
+    fn hour(time: u64) -> String {
+        let time = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(START + time));
+        time.format("%Y-%m-%d_%H").to_string()
+    }
+
+    #[test]
+    fn splits_by_hour_keeping_earlier_hours_open() {
+        // The third record is captured after a clock step back into the first hour
+        let times = [0, 3600 * SECOND, 10 * SECOND];
+        let (directory, path) = recording("hour", &times, 10);
+        let output = directory.join("out.rec").to_string_lossy().to_string();
+
+        run(&[path, output.clone(), "hour".to_string()]).unwrap();
+
+        assert_eq!(payloads(&part_path(&output, &hour(0))), [0, 2]);
+        assert_eq!(payloads(&part_path(&output, &hour(3600 * SECOND))), [1]);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 3);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn splits_by_input_and_at_the_size_limit() {
+        let times: Vec<u64> = (0..70).collect();
+        let (directory, path) = recording("size", &times, BUF_SIZE);
+        let output = directory.join("out.rec").to_string_lossy().to_string();
+
+        run(&[path.clone(), output.clone(), "input".to_string()]).unwrap();
+        assert_eq!(payloads(&part_path(&output, "input0")), (0..70).step_by(2).collect::<Vec<_>>());
+        assert_eq!(payloads(&part_path(&output, "input1")), (1..70).step_by(2).collect::<Vec<_>>());
+
+        // A part is full once its records reach a megabyte, the record reaching it stays in the part
+        let output = directory.join("sized.rec").to_string_lossy().to_string();
+        run(&[path, output.clone(), "size".to_string(), "1".to_string()]).unwrap();
+
+        let per_part = (1024 * 1024usize).div_ceil(BUF_SIZE + 26) as u8;
+        assert_eq!(payloads(&part_path(&output, "0000")), (0..per_part).collect::<Vec<_>>());
+        assert_eq!(payloads(&part_path(&output, "0001")), (per_part..2 * per_part).collect::<Vec<_>>());
+        assert_eq!(payloads(&part_path(&output, "0002")), (2 * per_part..70).collect::<Vec<_>>());
+
+        assert_eq!(part_path("out", "0001"), "out.0001");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
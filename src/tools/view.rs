@@ -0,0 +1,222 @@
+//! Print records of a recording with their times and a hex and ASCII dump of the payload.
+//! With `--follow` it keeps printing records as they are appended to a recording being written.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Error, ErrorKind, Write},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    format::{Record, RecordReader},
+    utils::parse_hex,
+};
+
+use super::{
+    report_corruption,
+    slice::{seek_before, SliceBound, SlicePosition},
+    usage,
+};
+
+const USAGE: &str = "view <recording> [<from> [<to>]] [--follow] [--find <hex>] [--no-dump]
+bounds are a time of day 09:15[:30.250], an offset from the first record +90s, a record number #1000 or -
+--find shows only records containing the bytes, --no-dump leaves out the payload";
+/// How often the end of a followed recording is checked for new records
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+const BYTES_PER_LINE: usize = 16;
+
+#[derive(Debug, Default)]
+struct Options {
+    from: Option<SliceBound>,
+    to: Option<SliceBound>,
+    follow: bool,
+    find: Option<Vec<u8>>,
+    dump: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, Error> {
+        let mut options = Options { dump: true, ..Default::default() };
+        let mut bounds = vec![];
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--follow" => options.follow = true,
+                "--no-dump" => options.dump = false,
+                "--find" => {
+                    let pattern = parse_hex(args.next().ok_or_else(|| usage(USAGE))?)?;
+                    if pattern.is_empty() {
+                        return Err(usage(USAGE));
+                    }
+                    options.find = Some(pattern);
+                }
+                _ if arg.starts_with("--") => return Err(usage(USAGE)),
+                _ => bounds.push(SliceBound::parse_arg(arg)?),
+            }
+        }
+
+        match bounds[..] {
+            [] => {}
+            [from] => options.from = from,
+            [from, to] => (options.from, options.to) = (from, to),
+            _ => return Err(usage(USAGE)),
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let [recording, args @ ..] = args else {
+        return Err(usage(USAGE));
+    };
+
+    let options = Options::parse(args)?;
+    let mut records = open(recording, options.follow)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    // The reader of `less` or `head` going away is a normal way to stop
+    match view(&mut records, recording, &options, &mut out).and_then(|_| out.flush()) {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+        result => result?,
+    }
+
+    report_corruption(&records, recording);
+
+    Ok(())
+}
+
+/// Open a recording, when following wait for its header to be written
+fn open(recording: &str, follow: bool) -> Result<RecordReader<BufReader<File>>, Error> {
+    loop {
+        match RecordReader::new(BufReader::with_capacity(131072, File::open(recording)?)) {
+            Err(e) if follow && e.kind() == ErrorKind::UnexpectedEof => thread::sleep(FOLLOW_INTERVAL),
+            records => return records,
+        }
+    }
+}
+
+fn view<W: Write>(
+    records: &mut RecordReader<BufReader<File>>,
+    recording: &str,
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Error> {
+    let Some(first) = next_record(records, options.follow, out)? else {
+        return Ok(());
+    };
+
+    let start = options.from.map(|from| SlicePosition::resolve(from, &first)).transpose()?;
+    let end = options.to.map(|to| SlicePosition::resolve(to, &first)).transpose()?;
+    seek_before(records, recording, start)?;
+
+    loop {
+        let number = records.position();
+        let Some(record) = next_record(records, options.follow, out)? else {
+            return Ok(());
+        };
+
+        if start.is_some_and(|start| !start.reached(&record, number)) {
+            continue;
+        }
+
+        if end.is_some_and(|end| end.reached(&record, number)) {
+            return Ok(());
+        }
+
+        let matches = match &options.find {
+            Some(pattern) => {
+                let matches = find_all(&record.data, pattern);
+                if matches.is_empty() {
+                    continue;
+                }
+                matches
+            }
+            None => vec![],
+        };
+
+        print_record(out, number, &record, &matches, options.dump)?;
+    }
+}
+
+/// Next record, when following wait for one to be written instead of stopping at the end
+fn next_record<W: Write>(
+    records: &mut RecordReader<BufReader<File>>,
+    follow: bool,
+    out: &mut W,
+) -> Result<Option<Record>, Error> {
+    loop {
+        match records.next_record()? {
+            None if follow => {
+                out.flush()?;
+                thread::sleep(FOLLOW_INTERVAL);
+            }
+            record => return Ok(record),
+        }
+    }
+}
+
+/// Offsets of all places the pattern occurs in the payload, overlapping ones included
+fn find_all(data: &[u8], pattern: &[u8]) -> Vec<usize> {
+    data.windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| *window == pattern)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+fn print_record<W: Write>(
+    out: &mut W,
+    number: u64,
+    record: &Record,
+    matches: &[usize],
+    dump: bool,
+) -> Result<(), Error> {
+    // Legacy recordings have no capture times, only the time since the first record
+    let time = match record.timestamp {
+        0 => format!("+{:.6}", record.elapsed as f64 / 1e9),
+        timestamp => DateTime::<Local>::from(UNIX_EPOCH + Duration::from_nanos(timestamp))
+            .format("%Y-%m-%d %H:%M:%S%.6f")
+            .to_string(),
+    };
+
+    writeln!(
+        out,
+        "#{} {} delta {:.6} size {} input {}",
+        number,
+        time,
+        record.delta as f64 / 1e9,
+        record.data.len(),
+        record.input
+    )?;
+
+    if !matches.is_empty() {
+        let offsets: Vec<String> = matches.iter().map(|offset| format!("{:#06x}", offset)).collect();
+        writeln!(out, "  found at {}", offsets.join(", "))?;
+    }
+
+    if dump {
+        for (line, bytes) in record.data.chunks(BYTES_PER_LINE).enumerate() {
+            let mut hex = String::new();
+            for (i, byte) in bytes.iter().enumerate() {
+                if i == BYTES_PER_LINE / 2 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x} ", byte));
+            }
+
+            let ascii: String = bytes
+                .iter()
+                .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+                .collect();
+
+            writeln!(out, "  {:04x}  {:<49} |{}|", line * BYTES_PER_LINE, hex, ascii)?;
+        }
+    }
+
+    Ok(())
+}
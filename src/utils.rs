@@ -1,3 +1,9 @@
+use std::{
+    env, fs,
+    io::{Error, ErrorKind},
+    net::Ipv4Addr,
+};
+
 pub fn u32_to_bytes(ms: u32) -> [u8; 4] {
     ms.to_be_bytes()
 }
@@ -5,3 +11,38 @@ pub fn u32_to_bytes(ms: u32) -> [u8; 4] {
 pub fn bytes_to_u32(bytes: [u8; 4]) -> u32 {
     u32::from_be_bytes(bytes)
 }
+
+/// Name of this machine, empty if it cannot be found
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .or_else(|_| env::var("HOSTNAME"))
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .unwrap_or_default()
+}
+
+/// IPv4 address of the setting `name`
+pub fn parse_ipv4(name: &str, ip: &str) -> Result<Ipv4Addr, Error> {
+    ip.parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid {} {:?}", name, ip)))
+}
+
+/// Bytes written as hex digits, spaces between bytes are allowed
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<u8> = hex.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid hex bytes {:?}", hex));
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}